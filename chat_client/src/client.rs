@@ -0,0 +1,266 @@
+use reqwest::{
+    multipart::{Form, Part},
+    Client, RequestBuilder, Response,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+
+use crate::{Chat, ClientError, ErrorOutput, Message, SignedFile, UploadOutput, User};
+
+/// Typed client of the chat_server api.
+#[derive(Debug, Clone)]
+pub struct ChatClient {
+    client: Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+/// a file to upload
+#[derive(Debug, Clone)]
+pub struct UploadFile {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthOutput {
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateChatOutput {
+    chat_id: i64,
+}
+
+impl ChatClient {
+    /// `base_url` is the root of chat_server, e.g. `http://localhost:8080`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        let base_url = base_url.into().trim_end_matches('/').to_string();
+        Self {
+            client: Client::new(),
+            base_url,
+            token: None,
+        }
+    }
+
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// create the user and keep its token for later requests
+    pub async fn signup(
+        &mut self,
+        workspace: &str,
+        fullname: &str,
+        email: &str,
+        password: &str,
+    ) -> Result<String, ClientError> {
+        let body = json!({
+            "workspace": workspace,
+            "fullname": fullname,
+            "email": email,
+            "password": password,
+        });
+        let req = self.client.post(self.url("/api/signup")).json(&body);
+        let ret: AuthOutput = send(req).await?;
+        self.token = Some(ret.token.clone());
+        Ok(ret.token)
+    }
+
+    /// sign in and keep the token for later requests
+    pub async fn signin(&mut self, email: &str, password: &str) -> Result<String, ClientError> {
+        let body = json!({ "email": email, "password": password });
+        let req = self.client.post(self.url("/api/signin")).json(&body);
+        let ret: AuthOutput = send(req).await?;
+        self.token = Some(ret.token.clone());
+        Ok(ret.token)
+    }
+
+    pub async fn me(&self) -> Result<User, ClientError> {
+        send(self.get("/api/me")?).await
+    }
+
+    pub async fn list_chats(&self) -> Result<Vec<Chat>, ClientError> {
+        send(self.get("/api/chats")?).await
+    }
+
+    /// return the id of the new chat
+    pub async fn create_chat(
+        &self,
+        name: Option<&str>,
+        members: &[i64],
+        public: bool,
+    ) -> Result<i64, ClientError> {
+        let body = json!({ "name": name, "members": members, "public": public });
+        let req = self.authed(self.client.post(self.url("/api/chats")))?;
+        let ret: CreateChatOutput = send(req.json(&body)).await?;
+        Ok(ret.chat_id)
+    }
+
+    /// messages before `last_id`, newest first
+    pub async fn list_messages(
+        &self,
+        chat_id: i64,
+        last_id: Option<u64>,
+        limit: u64,
+    ) -> Result<Vec<Message>, ClientError> {
+        let mut query = vec![("limit", limit)];
+        if let Some(last_id) = last_id {
+            query.push(("last_id", last_id));
+        }
+        let req = self.get(&format!("/api/{chat_id}/messages"))?;
+        send(req.query(&query)).await
+    }
+
+    pub async fn upload(&self, files: Vec<UploadFile>) -> Result<Vec<UploadOutput>, ClientError> {
+        let form = files.into_iter().fold(Form::new(), |form, file| {
+            form.part("file", Part::bytes(file.data).file_name(file.filename))
+        });
+        let req = self.authed(self.client.post(self.url("/api/upload")))?;
+        send(req.multipart(form)).await
+    }
+
+    /// sign the `/files/...` urls of messages or avatars, e.g. for `<img src>`
+    pub async fn sign_files(&self, urls: &[&str]) -> Result<Vec<SignedFile>, ClientError> {
+        let req = self.authed(self.client.post(self.url("/api/files/sign")))?;
+        send(req.json(&json!({ "urls": urls }))).await
+    }
+
+    /// download a file by its url, either `/files/...` from upload or a signed url
+    pub async fn download(&self, url: &str) -> Result<Vec<u8>, ClientError> {
+        let url = url.trim_start_matches("/api");
+        let req = self.client.get(self.url(&format!("/api{url}")));
+        // signed urls carry their own credentials
+        let req = match (&self.token, url.contains("sig=")) {
+            (Some(token), false) => req.bearer_auth(token),
+            (None, false) => return Err(ClientError::Unauthenticated),
+            _ => req,
+        };
+        let res = check(req.send().await?).await?;
+        Ok(res.bytes().await?.to_vec())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    fn get(&self, path: &str) -> Result<RequestBuilder, ClientError> {
+        self.authed(self.client.get(self.url(path)))
+    }
+
+    fn authed(&self, req: RequestBuilder) -> Result<RequestBuilder, ClientError> {
+        let token = self.token.as_ref().ok_or(ClientError::Unauthenticated)?;
+        Ok(req.bearer_auth(token))
+    }
+}
+
+async fn send<T: DeserializeOwned>(req: RequestBuilder) -> Result<T, ClientError> {
+    let res = check(req.send().await?).await?;
+    Ok(res.json().await?)
+}
+
+/// turn error responses into `ClientError::Api`
+async fn check(res: Response) -> Result<Response, ClientError> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    let body = res.text().await?;
+    let err =
+        serde_json::from_str::<ErrorOutput>(&body).unwrap_or_else(|_| ErrorOutput::new("", body));
+    Err(ClientError::Api {
+        status: status.as_u16(),
+        code: err.code,
+        error: err.error,
+        request_id: err.request_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use anyhow::Result;
+    use axum::{
+        http::{HeaderMap, StatusCode},
+        response::IntoResponse,
+        routing::{get, post},
+        Json, Router,
+    };
+    use chrono::Utc;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::ChatType;
+
+    async fn chats_handler(headers: HeaderMap) -> impl IntoResponse {
+        if headers.get("authorization").and_then(|v| v.to_str().ok()) != Some("Bearer token") {
+            let body = json!({ "code": "jwt_error", "error": "bad token", "request_id": "req-1" });
+            return (StatusCode::FORBIDDEN, Json(body)).into_response();
+        }
+        let chat = Chat {
+            id: 1,
+            ws_id: 1,
+            name: Some("general".to_string()),
+            r#type: ChatType::PublicChannel,
+            members: vec![1, 2],
+            created_at: Utc::now(),
+        };
+        Json(vec![chat]).into_response()
+    }
+
+    async fn start_server() -> Result<SocketAddr> {
+        let app = Router::new()
+            .route(
+                "/api/signin",
+                post(|| async { Json(json!({ "token": "token" })) }),
+            )
+            .route("/api/chats", get(chats_handler));
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn client_should_sign_in_and_list_chats() -> Result<()> {
+        let addr = start_server().await?;
+        let mut client = ChatClient::new(format!("http://{addr}/"));
+        assert!(matches!(
+            client.list_chats().await,
+            Err(ClientError::Unauthenticated)
+        ));
+
+        client.signin("tchen@acme.org", "123456").await?;
+        assert_eq!(client.token(), Some("token"));
+        let chats = client.list_chats().await?;
+        assert_eq!(chats.len(), 1);
+        assert_eq!(chats[0].r#type, ChatType::PublicChannel);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_should_return_api_error() -> Result<()> {
+        let addr = start_server().await?;
+        let client = ChatClient::new(format!("http://{addr}")).with_token("bad");
+        match client.list_chats().await {
+            Err(ClientError::Api {
+                status,
+                code,
+                request_id,
+                ..
+            }) => {
+                assert_eq!(status, 403);
+                assert_eq!(code, "jwt_error");
+                assert_eq!(request_id.as_deref(), Some("req-1"));
+            }
+            ret => panic!("expect api error, got {ret:?}"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("api error {status} {code}: {error}")]
+    Api {
+        status: u16,
+        /// stable code of the server error, e.g. `email_already_exists`
+        code: String,
+        error: String,
+        request_id: Option<String>,
+    },
+
+    #[error("not signed in")]
+    Unauthenticated,
+
+    #[error("event stream error: {0}")]
+    EventStream(String),
+
+    #[error("invalid event: {0}")]
+    InvalidEvent(#[from] serde_json::Error),
+}
@@ -0,0 +1,79 @@
+use futures::{Stream, StreamExt};
+use reqwest_eventsource::{Event, EventSource};
+
+use crate::{AppEvent, ClientError};
+
+/// Subscribe to the events of the signed in user on notify_server.
+///
+/// `notify_url` is the root of notify_server, events are read from its `/events` sse endpoint,
+/// each message carries an `AppEvent` as json. The stream reconnects on its own, connection
+/// errors are yielded and the caller decides whether to keep polling.
+pub fn subscribe(
+    notify_url: &str,
+    token: &str,
+) -> Result<impl Stream<Item = Result<AppEvent, ClientError>>, ClientError> {
+    let url = format!("{}/events", notify_url.trim_end_matches('/'));
+    let req = reqwest::Client::new().get(url).bearer_auth(token);
+    let source = EventSource::new(req).map_err(|e| ClientError::EventStream(e.to_string()))?;
+
+    let stream = source.filter_map(|event| async move {
+        match event {
+            Ok(Event::Open) => None,
+            Ok(Event::Message(msg)) => Some(serde_json::from_str(&msg.data).map_err(Into::into)),
+            Err(e) => Some(Err(ClientError::EventStream(e.to_string()))),
+        }
+    });
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use anyhow::Result;
+    use axum::{
+        response::sse::{Event as SseEvent, Sse},
+        routing::get,
+        Router,
+    };
+    use chrono::Utc;
+    use futures::stream;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::Message;
+
+    fn message() -> AppEvent {
+        AppEvent::NewMessage(Message {
+            id: 1,
+            chat_id: 1,
+            sender_id: 1,
+            content: "hello".to_string(),
+            files: vec![],
+            created_at: Utc::now(),
+        })
+    }
+
+    #[tokio::test]
+    async fn subscribe_should_yield_app_events() -> Result<()> {
+        let app = Router::new().route(
+            "/events",
+            get(|| async {
+                let event = message();
+                let sse = SseEvent::default()
+                    .event(event.name())
+                    .json_data(&event)
+                    .unwrap();
+                Sse::new(stream::iter([Ok::<_, Infallible>(sse)]))
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut events = Box::pin(subscribe(&format!("http://{addr}"), "token")?);
+        let event = events.next().await.unwrap()?;
+        assert_eq!(event.name(), "new_message");
+        Ok(())
+    }
+}
@@ -0,0 +1,10 @@
+mod client;
+mod error;
+mod events;
+
+pub use chat_core::{
+    AppEvent, Chat, ChatType, ErrorOutput, Message, SignedFile, UploadOutput, User,
+};
+pub use client::{ChatClient, UploadFile};
+pub use error::ClientError;
+pub use events::subscribe;
@@ -0,0 +1,100 @@
+use std::{str::FromStr, time::Duration};
+
+use anyhow::{Context, Result};
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    PgPool,
+};
+use tracing::warn;
+
+use crate::DbConfig;
+
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+impl DbConfig {
+    /// pool of the primary
+    pub async fn connect(&self) -> Result<PgPool> {
+        self.connect_url(&self.url).await
+    }
+
+    /// pool of the read replica, or the primary if not configured
+    pub async fn connect_read(&self, primary: &PgPool) -> Result<PgPool> {
+        match &self.read_url {
+            Some(url) => self.connect_url(url).await,
+            None => Ok(primary.clone()),
+        }
+    }
+
+    /// connect with an exponential backoff, so the server can start before postgres is up
+    async fn connect_url(&self, url: &str) -> Result<PgPool> {
+        let options = self.connect_options(url)?;
+        let mut delay = BACKOFF_BASE;
+        for attempt in 1..self.connect_attempts {
+            match self.pool_options().connect_with(options.clone()).await {
+                Ok(pool) => return Ok(pool),
+                Err(e) => {
+                    warn!(attempt, "connect to db failed, retrying in {delay:?}: {e}");
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(BACKOFF_MAX);
+                }
+            }
+        }
+        // the last attempt returns its error
+        self.pool_options()
+            .connect_with(options)
+            .await
+            .context("connect to db failed")
+    }
+
+    fn connect_options(&self, url: &str) -> Result<PgConnectOptions> {
+        let mut options = PgConnectOptions::from_str(url).context("invalid db url")?;
+        if self.statement_timeout > 0 {
+            let timeout = self.statement_timeout.to_string();
+            options = options.options([("statement_timeout", timeout.as_str())]);
+        }
+        Ok(options)
+    }
+
+    fn pool_options(&self) -> PgPoolOptions {
+        let idle_timeout = (self.idle_timeout > 0).then(|| Duration::from_secs(self.idle_timeout));
+        PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .acquire_timeout(Duration::from_secs(self.acquire_timeout))
+            .idle_timeout(idle_timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn connect_should_retry_with_backoff() {
+        let config = DbConfig {
+            // nothing listens on the port
+            url: "postgres://postgres@127.0.0.1:1/chat".to_string(),
+            connect_attempts: 3,
+            acquire_timeout: 1,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let err = config.connect().await.unwrap_err();
+        assert_eq!(err.to_string(), "connect to db failed");
+        // 500ms + 1s between the attempts
+        assert!(start.elapsed() >= Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn connect_options_should_set_statement_timeout() -> Result<()> {
+        let config = DbConfig {
+            statement_timeout: 5000,
+            ..Default::default()
+        };
+        let options = config.connect_options("postgres://postgres@localhost/chat")?;
+        assert_eq!(options.get_options(), Some("-c statement_timeout=5000"));
+        Ok(())
+    }
+}
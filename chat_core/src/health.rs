@@ -0,0 +1,109 @@
+use std::{fmt::Display, future::Future, time::Duration};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use tokio::time::Instant;
+use tracing::warn;
+
+/// a check slower than this counts as failed so a hung dependency can't hang the probe
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// body of `/healthz` and `/readyz`, 503 if any check failed
+#[derive(Debug, Serialize)]
+pub struct HealthOutput {
+    pub status: &'static str,
+    pub checks: Vec<CheckOutput>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckOutput {
+    pub name: &'static str,
+    pub ok: bool,
+    pub latency_ms: u64,
+    /// a fixed message, the probe is unauthenticated so the cause is only logged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+}
+
+impl HealthOutput {
+    pub fn new(checks: Vec<CheckOutput>) -> Self {
+        let status = if checks.iter().all(|c| c.ok) {
+            "ok"
+        } else {
+            "fail"
+        };
+        Self { status, checks }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|c| c.ok)
+    }
+}
+
+impl IntoResponse for HealthOutput {
+    fn into_response(self) -> Response {
+        let status = if self.is_ok() {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+/// run a readiness check and time it
+pub async fn check<F, E>(name: &'static str, fut: F) -> CheckOutput
+where
+    F: Future<Output = Result<(), E>>,
+    E: Display,
+{
+    let start = Instant::now();
+    let error = match tokio::time::timeout(CHECK_TIMEOUT, fut).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => {
+            warn!(check = name, "readiness check failed: {e}");
+            Some("unavailable")
+        }
+        Err(_) => {
+            warn!(
+                check = name,
+                "readiness check timed out after {CHECK_TIMEOUT:?}"
+            );
+            Some("timed out")
+        }
+    };
+    CheckOutput {
+        name,
+        ok: error.is_none(),
+        latency_ms: start.elapsed().as_millis() as u64,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn health_output_should_fail_if_any_check_fails() {
+        let checks = vec![
+            check("db", async { Ok::<_, String>(()) }).await,
+            check("storage", async {
+                Err("connect to postgres://chat@db-internal:5432 failed".to_string())
+            })
+            .await,
+        ];
+        let output = HealthOutput::new(checks);
+        assert_eq!(output.status, "fail");
+        // the cause is only logged
+        assert_eq!(output.checks[1].error, Some("unavailable"));
+        assert_eq!(
+            output.into_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use jwt_simple::prelude::*;
+
+use crate::User;
+
+const JWT_DURATION: u64 = 60 * 60 * 24 * 7;
+const JWT_ISS: &str = "chat_server";
+const JWT_AUD: &str = "chat_web";
+
+pub struct EncodingKey(Ed25519KeyPair);
+
+pub struct DecodingKey(Ed25519PublicKey);
+
+/// the verifying keys accepted at the moment, swapped on reload so tokens signed by a
+/// previous key stay valid while rotating
+pub struct DecodingKeys(ArcSwap<Vec<DecodingKey>>);
+
+impl EncodingKey {
+    pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
+        Ok(Self(Ed25519KeyPair::from_pem(pem)?))
+    }
+
+    /// a new random key, e.g. for a tool which never hands out its tokens
+    pub fn generate() -> Self {
+        Self(Ed25519KeyPair::generate())
+    }
+
+    pub fn sign(&self, user: impl Into<User>) -> Result<String, jwt_simple::Error> {
+        let claims = Claims::with_custom_claims(user.into(), Duration::from_secs(JWT_DURATION));
+        let claims = claims.with_issuer(JWT_ISS).with_audience(JWT_AUD);
+        self.0.sign(claims)
+    }
+}
+
+impl DecodingKey {
+    pub fn load(pem: &str) -> Result<Self, jwt_simple::Error> {
+        Ok(Self(Ed25519PublicKey::from_pem(pem)?))
+    }
+
+    pub fn verify(&self, token: &str) -> Result<User, jwt_simple::Error> {
+        let opts = VerificationOptions {
+            allowed_issuers: Some(HashSet::from_strings(&[JWT_ISS])),
+            allowed_audiences: Some(HashSet::from_strings(&[JWT_AUD])),
+            ..Default::default()
+        };
+
+        let claims = self.0.verify_token::<User>(token, Some(opts))?;
+        Ok(claims.custom)
+    }
+}
+
+impl DecodingKeys {
+    pub fn new(keys: Vec<DecodingKey>) -> Self {
+        Self(ArcSwap::from_pointee(keys))
+    }
+
+    /// load every pem, fails if any of them is invalid
+    pub fn load_all<'a>(
+        pems: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Vec<DecodingKey>, jwt_simple::Error> {
+        pems.into_iter().map(DecodingKey::load).collect()
+    }
+
+    pub fn store(&self, keys: Vec<DecodingKey>) {
+        self.0.store(Arc::new(keys));
+    }
+
+    /// the user of a token verified by any of the keys
+    pub fn verify(&self, token: &str) -> Result<User, jwt_simple::Error> {
+        let mut error = None;
+        for key in self.0.load().iter() {
+            match key.verify(token) {
+                Ok(user) => return Ok(user),
+                Err(e) => error = Some(e),
+            }
+        }
+        Err(error.unwrap_or_else(|| jwt_simple::Error::msg("no verifying key")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn jwt_sign_verify_should_work() -> Result<()> {
+        let key_pair = Ed25519KeyPair::generate();
+        let ek = EncodingKey::load(&key_pair.to_pem())?;
+        let dk = DecodingKey::load(&key_pair.public_key().to_pem())?;
+
+        let user = User::new(1, "Tyr Chen", "tchen@acme.org");
+
+        let token = ek.sign(user.clone())?;
+        let user2 = dk.verify(&token)?;
+
+        assert_eq!(user, user2);
+        Ok(())
+    }
+
+    #[test]
+    fn decoding_keys_should_accept_any_key() -> Result<()> {
+        let old = Ed25519KeyPair::generate();
+        let new = Ed25519KeyPair::generate();
+        let user = User::new(1, "Tyr Chen", "tchen@acme.org");
+        let old_token = EncodingKey::load(&old.to_pem())?.sign(user.clone())?;
+        let new_token = EncodingKey::load(&new.to_pem())?.sign(user.clone())?;
+
+        let dks = DecodingKeys::new(DecodingKeys::load_all([old
+            .public_key()
+            .to_pem()
+            .as_str()])?);
+        assert!(dks.verify(&new_token).is_err());
+
+        let pems = [new.public_key().to_pem(), old.public_key().to_pem()];
+        dks.store(DecodingKeys::load_all(pems.iter().map(|pem| pem.as_str()))?);
+        assert_eq!(dks.verify(&new_token)?, user);
+        assert_eq!(dks.verify(&old_token)?, user);
+
+        dks.store(DecodingKeys::load_all([pems[0].as_str()])?);
+        assert!(dks.verify(&old_token).is_err());
+        Ok(())
+    }
+}
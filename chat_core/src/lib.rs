@@ -0,0 +1,22 @@
+mod config;
+mod db;
+mod health;
+mod jwt;
+mod metrics;
+mod models;
+mod reload;
+mod shutdown;
+mod telemetry;
+
+pub use config::*;
+pub use health::{check, CheckOutput, HealthOutput};
+pub use jwt::{DecodingKey, DecodingKeys, EncodingKey};
+pub use metrics::{metrics_handle, render_metrics, track_metrics};
+pub use models::*;
+pub use reload::spawn_reloader;
+pub use shutdown::Shutdown;
+pub use telemetry::{
+    current_request_id, current_traceparent, init_tracing, make_request_span,
+    set_parent_from_traceparent, LogConfig, LogFileConfig, LogFilter, LogFormat, LogRotation,
+    TelemetryConfig, TelemetryGuard, REQUEST_ID,
+};
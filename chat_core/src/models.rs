@@ -0,0 +1,161 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::current_request_id;
+
+#[derive(Debug, Clone, FromRow, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct User {
+    pub id: i64,
+    pub ws_id: i64,
+    pub fullname: String,
+    pub email: String,
+    #[sqlx(default)]
+    pub avatar_url: Option<String>,
+    /// bumped to revoke all issued tokens of the user
+    #[sqlx(default)]
+    #[serde(default)]
+    pub token_version: i32,
+    #[sqlx(default)]
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, PartialOrd, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "chat_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ChatType {
+    Single,
+    Group,
+    PrivateChannel,
+    PublicChannel,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct Chat {
+    pub id: i64,
+    pub ws_id: i64,
+    pub name: Option<String>,
+    pub r#type: ChatType,
+    pub members: Vec<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct Message {
+    pub id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub content: String,
+    pub files: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// body of every chat_server error response
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ErrorOutput {
+    /// stable machine-readable code, see `AppError::code`
+    #[serde(default)]
+    pub code: String,
+    pub error: String,
+    /// extra payload, e.g. the messages of each invalid field
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<Value>,
+    /// the `x-request-id` of the failed request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct UploadOutput {
+    pub url: String,
+    /// url usable without a bearer token until it expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct SignedFile {
+    pub url: String,
+    /// url usable without a bearer token until it expires
+    pub signed_url: String,
+}
+
+/// Events pushed by notify_server, the sse event name is `AppEvent::name`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum AppEvent {
+    NewChat(Chat),
+    AddToChat(Chat),
+    RemoveFromChat(Chat),
+    NewMessage(Message),
+    /// sent before notify_server shuts down, clients should reconnect
+    ServerRestarting,
+}
+
+impl ErrorOutput {
+    pub fn new(code: impl Into<String>, error: impl Into<String>) -> Self {
+        Self {
+            code: code.into(),
+            error: error.into(),
+            details: None,
+            request_id: current_request_id(),
+        }
+    }
+}
+
+impl User {
+    pub fn new(id: i64, fullname: &str, email: &str) -> Self {
+        Self {
+            id,
+            ws_id: 0,
+            fullname: fullname.to_string(),
+            email: email.to_string(),
+            avatar_url: None,
+            token_version: 0,
+            password_hash: None,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+impl AppEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AppEvent::NewChat(_) => "new_chat",
+            AppEvent::AddToChat(_) => "add_to_chat",
+            AppEvent::RemoveFromChat(_) => "remove_from_chat",
+            AppEvent::NewMessage(_) => "new_message",
+            AppEvent::ServerRestarting => "server_restarting",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn app_event_should_round_trip() {
+        let event = AppEvent::NewMessage(Message {
+            id: 1,
+            chat_id: 1,
+            sender_id: 1,
+            content: "hello".to_string(),
+            files: vec![],
+            created_at: Utc::now(),
+        });
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["event"], event.name());
+        let event2: AppEvent = serde_json::from_value(json).unwrap();
+        assert_eq!(event, event2);
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    future::Future,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use metrics::counter;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// run `reload` on SIGHUP and whenever one of the watched files is modified, checked every
+/// `interval`. A failed reload is logged and the current config stays in effect
+pub fn spawn_reloader<F, Fut>(watch: Vec<PathBuf>, interval: Duration, reload: F) -> JoinHandle<()>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send,
+{
+    tokio::spawn(async move {
+        let mut hangup = hangup();
+        let mut ticker = tokio::time::interval(interval);
+        let mut modified = modified_times(&watch).await;
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => info!("SIGHUP received, reloading config"),
+                _ = ticker.tick() => {
+                    let current = modified_times(&watch).await;
+                    if current == modified {
+                        continue;
+                    }
+                    modified = current;
+                    info!("config files changed, reloading config");
+                }
+            }
+
+            match reload().await {
+                Ok(()) => {
+                    counter!("config_reloads_total", "result" => "ok").increment(1);
+                    info!("config reloaded");
+                }
+                Err(e) => {
+                    counter!("config_reloads_total", "result" => "rejected").increment(1);
+                    error!("config reload rejected, keeping the current config: {e:#}");
+                }
+            }
+        }
+    })
+}
+
+async fn modified_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    let mut times = Vec::with_capacity(paths.len());
+    for path in paths {
+        let time = tokio::fs::metadata(path).await.and_then(|m| m.modified());
+        times.push(time.ok());
+    }
+    times
+}
+
+#[cfg(unix)]
+fn hangup() -> tokio::signal::unix::Signal {
+    use tokio::signal::unix::{signal, SignalKind};
+    signal(SignalKind::hangup()).expect("failed to install SIGHUP handler")
+}
+
+#[cfg(not(unix))]
+fn hangup() -> NoSignal {
+    NoSignal
+}
+
+#[cfg(not(unix))]
+struct NoSignal;
+
+#[cfg(not(unix))]
+impl NoSignal {
+    async fn recv(&mut self) -> Option<()> {
+        std::future::pending().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use anyhow::bail;
+
+    #[tokio::test]
+    async fn reloader_should_run_on_file_change() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("reload-{}.yaml", std::process::id()));
+        tokio::fs::write(&path, "a: 1").await?;
+
+        let reloads = Arc::new(AtomicUsize::new(0));
+        let counter = reloads.clone();
+        let handle = spawn_reloader(vec![path.clone()], Duration::from_millis(10), move || {
+            let counter = counter.clone();
+            async move {
+                // the rejected reload keeps the reloader running
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    bail!("invalid config");
+                }
+                Ok(())
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(reloads.load(Ordering::SeqCst), 0);
+
+        for expected in 1..=2 {
+            // the mtime resolution of some file systems is coarse
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let file = std::fs::OpenOptions::new().append(true).open(&path)?;
+            file.set_modified(SystemTime::now() + Duration::from_secs(expected))?;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(reloads.load(Ordering::SeqCst), expected as usize);
+        }
+
+        handle.abort();
+        tokio::fs::remove_file(path).await?;
+        Ok(())
+    }
+}
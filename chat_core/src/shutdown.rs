@@ -0,0 +1,70 @@
+use std::{future::IntoFuture, io, time::Duration};
+
+use tokio::{signal, sync::watch};
+use tracing::{info, warn};
+
+/// resolves once the process receives SIGINT or SIGTERM, clones share the same signal
+#[derive(Debug, Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// start listening to SIGINT and SIGTERM
+    pub fn listen() -> Self {
+        let (tx, rx) = watch::channel(false);
+        tokio::spawn(async move {
+            wait_signal().await;
+            info!("shutdown signal received");
+            let _ = tx.send(true);
+        });
+        Self(rx)
+    }
+
+    pub async fn wait(mut self) {
+        let _ = self.0.wait_for(|done| *done).await;
+    }
+
+    /// run a server started `with_graceful_shutdown(self.wait())` and give the in-flight
+    /// connections `deadline` to finish after the signal, the rest are dropped
+    pub async fn drain<S>(self, server: S, deadline: Duration) -> io::Result<()>
+    where
+        S: IntoFuture<Output = io::Result<()>>,
+    {
+        let server = server.into_future();
+        tokio::pin!(server);
+        tokio::select! {
+            res = &mut server => return res,
+            _ = self.wait() => {}
+        }
+        match tokio::time::timeout(deadline, server).await {
+            Ok(res) => res,
+            Err(_) => {
+                warn!("connections not drained within {deadline:?}, dropping them");
+                Ok(())
+            }
+        }
+    }
+}
+
+async fn wait_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
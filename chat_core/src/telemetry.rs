@@ -0,0 +1,413 @@
+use std::{collections::HashMap, env, path::PathBuf};
+
+use anyhow::Result;
+use axum::{extract::Request, http::HeaderMap};
+use opentelemetry::{propagation::TextMapPropagator, trace::TracerProvider as _, KeyValue};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator, runtime, trace::TracerProvider, Resource,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info_span, warn, Span, Subscriber};
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    fmt::{Layer, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer as _, Registry,
+};
+
+use crate::ConfigErrors;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogConfig {
+    /// filter directives of the logs and exported spans, e.g. `info,chat_server=debug`,
+    /// `RUST_LOG` wins if set
+    #[serde(default = "default_log_filter")]
+    pub filter: String,
+    #[serde(default)]
+    pub format: LogFormat,
+    /// also write the logs to rolling files
+    #[serde(default)]
+    pub file: Option<LogFileConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// one json object per line, the event fields at the top level
+    Json,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogFileConfig {
+    pub dir: PathBuf,
+    /// file name prefix, the service name if not set
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub rotation: LogRotation,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+fn default_log_filter() -> String {
+    "info".to_string()
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filter: default_log_filter(),
+            format: LogFormat::default(),
+            file: None,
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn validate(&self, errors: &mut ConfigErrors) {
+        errors.check("log.filter", EnvFilter::try_new(&self.filter).map(|_| ()));
+        if let Some(directives) = rust_log() {
+            errors.check("RUST_LOG", EnvFilter::try_new(directives).map(|_| ()));
+        }
+        if let Some(file) = &self.file {
+            if file.dir.as_os_str().is_empty() {
+                errors.add("log.file.dir", "must be set");
+            }
+        }
+    }
+
+    /// the effective filter directives, `RUST_LOG` or `filter`
+    pub fn directives(&self) -> String {
+        rust_log().unwrap_or_else(|| self.filter.clone())
+    }
+}
+
+fn rust_log() -> Option<String> {
+    env::var("RUST_LOG").ok().filter(|v| !v.is_empty())
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Minutely => Rotation::MINUTELY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TelemetryConfig {
+    /// otlp/http traces endpoint, e.g. http://localhost:4318/v1/traces, spans are only
+    /// logged if not set
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+}
+
+/// flushes the pending spans when dropped, keep it until the server quits
+pub struct TelemetryGuard {
+    provider: TracerProvider,
+    filter: LogFilter,
+    // flushes the buffered file logs when dropped
+    _file: Option<WorkerGuard>,
+}
+
+impl TelemetryGuard {
+    pub fn log_filter(&self) -> LogFilter {
+        self.filter.clone()
+    }
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            warn!("shutdown tracer provider failed: {e}");
+        }
+    }
+}
+
+/// handle to swap the filter of the installed subscriber on reload
+#[derive(Clone)]
+pub struct LogFilter(reload::Handle<EnvFilter, Registry>);
+
+impl LogFilter {
+    /// parse the directives without applying them, so a reload can be rejected as a whole
+    pub fn parse(directives: &str) -> Result<EnvFilter> {
+        Ok(EnvFilter::try_new(directives)?)
+    }
+
+    pub fn set(&self, filter: EnvFilter) -> Result<()> {
+        Ok(self.0.reload(filter)?)
+    }
+}
+
+/// log to stdout, and to rolling files if configured, filtered by `log.filter` and export the
+/// spans of `service` to the otlp endpoint if set. Trace ids are generated either way so they
+/// propagate through `traceparent`
+pub fn init_tracing(
+    service: &'static str,
+    log: &LogConfig,
+    config: &TelemetryConfig,
+) -> Result<TelemetryGuard> {
+    let provider = build_tracer_provider(service, config.otlp_endpoint.as_deref())?;
+    let (filter, handle) = reload::Layer::new(LogFilter::parse(&log.directives())?);
+    let mut layers = vec![fmt_layer(log.format, std::io::stdout, true)];
+    let file = match &log.file {
+        Some(file) => {
+            let (writer, guard) = tracing_appender::non_blocking(file_appender(service, file)?);
+            layers.push(fmt_layer(log.format, writer, false));
+            Some(guard)
+        }
+        None => None,
+    };
+    let otel = tracing_opentelemetry::layer().with_tracer(provider.tracer(service));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layers)
+        .with(otel)
+        .init();
+    Ok(TelemetryGuard {
+        provider,
+        filter: LogFilter(handle),
+        _file: file,
+    })
+}
+
+fn fmt_layer<S, W>(
+    format: LogFormat,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn tracing_subscriber::Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = Layer::new().with_writer(writer).with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().flatten_event(true).boxed(),
+    }
+}
+
+fn file_appender(service: &str, config: &LogFileConfig) -> Result<RollingFileAppender> {
+    let prefix = config.prefix.as_deref().unwrap_or(service);
+    let appender = RollingFileAppender::builder()
+        .rotation(config.rotation.into())
+        .filename_prefix(prefix)
+        .filename_suffix("log")
+        .build(&config.dir)?;
+    Ok(appender)
+}
+
+pub fn build_tracer_provider(
+    service: &'static str,
+    otlp_endpoint: Option<&str>,
+) -> Result<TracerProvider> {
+    let mut builder = TracerProvider::builder()
+        .with_resource(Resource::new([KeyValue::new("service.name", service)]));
+    if let Some(endpoint) = otlp_endpoint {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()?;
+        builder = builder.with_batch_exporter(exporter, runtime::Tokio);
+    }
+    Ok(builder.build())
+}
+
+/// span of an http request, a child of the incoming `traceparent` if any. `request_id` is
+/// recorded once the request id is known. The query is left out, it may carry the signature
+/// of a signed url
+pub fn make_request_span(req: &Request) -> Span {
+    let span = info_span!(
+        "request",
+        method = %req.method(),
+        path = req.uri().path(),
+        version = ?req.version(),
+        request_id = tracing::field::Empty,
+    );
+    set_parent_from_headers(&span, req.headers());
+    span
+}
+
+pub fn set_parent_from_headers(span: &Span, headers: &HeaderMap) {
+    let cx = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    span.set_parent(cx);
+}
+
+pub fn set_parent_from_traceparent(span: &Span, traceparent: &str) {
+    let carrier = HashMap::from([("traceparent".to_string(), traceparent.to_string())]);
+    let cx = TraceContextPropagator::new().extract(&carrier);
+    span.set_parent(cx);
+}
+
+tokio::task_local! {
+    /// request id of the request being handled, echoed in error bodies
+    pub static REQUEST_ID: String;
+}
+
+/// request id scoped by chat_server's `set_request_id`, none outside of a request
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// w3c `traceparent` of the current span, none if it isn't sampled
+pub fn current_traceparent() -> Option<String> {
+    let cx = Span::current().context();
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&cx, &mut carrier);
+    carrier.remove("traceparent")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use axum::{http::header, routing::post, Router};
+    use opentelemetry::trace::Tracer;
+    use tokio::net::TcpListener;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn traceparent_should_propagate_to_child_spans() {
+        let provider = build_tracer_provider("test", None).unwrap();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!("notification");
+            set_parent_from_traceparent(&span, TRACEPARENT);
+            let _enter = span.enter();
+            let traceparent = current_traceparent().unwrap();
+            assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+            assert_ne!(traceparent, TRACEPARENT);
+        });
+    }
+
+    #[test]
+    fn log_filter_should_be_swapped() -> Result<()> {
+        let mut errors = ConfigErrors::default();
+        let log = LogConfig {
+            filter: "info,chat_server=loud".to_string(),
+            ..Default::default()
+        };
+        log.validate(&mut errors);
+        assert!(errors.into_result().is_err());
+
+        let (filter, handle) = reload::Layer::new(LogFilter::parse("info")?);
+        let subscriber = tracing_subscriber::registry().with(filter);
+        let filter = LogFilter(handle);
+        tracing::subscriber::with_default(subscriber, || -> Result<()> {
+            assert!(!tracing::enabled!(tracing::Level::DEBUG));
+            filter.set(LogFilter::parse("debug")?)?;
+            assert!(tracing::enabled!(tracing::Level::DEBUG));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn json_logs_should_carry_fields_to_rolling_files() -> Result<()> {
+        let log: LogConfig = serde_yaml::from_str(&format!(
+            "format: json\nfile:\n  dir: {}\n  rotation: never",
+            std::env::temp_dir()
+                .join(format!("logs-{}", std::process::id()))
+                .display()
+        ))?;
+        let file = log.file.as_ref().unwrap();
+        let (writer, guard) = tracing_appender::non_blocking(file_appender("test", file)?);
+        let subscriber = tracing_subscriber::registry().with(fmt_layer(log.format, writer, false));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!("request", request_id = "0192");
+            let _enter = span.enter();
+            tracing::info!(user_id = 1, chat_id = 2, "verify chat");
+        });
+        drop(guard);
+
+        let content = std::fs::read_to_string(file.dir.join("test.log"))?;
+        let line: serde_json::Value = serde_json::from_str(content.trim())?;
+        assert_eq!(line["message"], "verify chat");
+        assert_eq!(line["user_id"], 1);
+        assert_eq!(line["chat_id"], 2);
+        assert_eq!(line["span"]["request_id"], "0192");
+        std::fs::remove_dir_all(&file.dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn request_span_should_leave_out_the_query() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("span-logs-{}", std::process::id()));
+        let file = LogFileConfig {
+            dir: dir.clone(),
+            prefix: None,
+            rotation: LogRotation::Never,
+        };
+        let (writer, guard) = tracing_appender::non_blocking(file_appender("test", &file)?);
+        let subscriber =
+            tracing_subscriber::registry().with(fmt_layer(LogFormat::Json, writer, false));
+        tracing::subscriber::with_default(subscriber, || -> Result<()> {
+            let req = Request::get("/files/1/a.txt?expires=1&user=1&sig=secret")
+                .body(axum::body::Body::empty())?;
+            let span = make_request_span(&req);
+            let _enter = span.enter();
+            tracing::info!("file served");
+            Ok(())
+        })?;
+        drop(guard);
+
+        let content = std::fs::read_to_string(dir.join("test.log"))?;
+        let line: serde_json::Value = serde_json::from_str(content.trim())?;
+        assert_eq!(line["span"]["path"], "/files/1/a.txt");
+        assert!(!content.contains("secret"));
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spans_should_be_exported_to_otlp_endpoint() -> Result<()> {
+        // a collector stub counting the protobuf export requests
+        let exports = Arc::new(AtomicUsize::new(0));
+        let counter = exports.clone();
+        let app = Router::new().route(
+            "/v1/traces",
+            post(move |headers: HeaderMap| async move {
+                assert_eq!(headers[header::CONTENT_TYPE], "application/x-protobuf");
+                counter.fetch_add(1, Ordering::SeqCst);
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let provider = build_tracer_provider("test", Some(&endpoint))?;
+        provider.tracer("test").in_span("create_chat", |_| {});
+        for result in provider.force_flush() {
+            result?;
+        }
+        assert_eq!(exports.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+}
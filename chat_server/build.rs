@@ -0,0 +1,4 @@
+fn main() {
+    // embed the migrations added since the last build
+    println!("cargo:rerun-if-changed=../migrations");
+}
@@ -34,3 +34,11 @@ INSERT INTO messages(chat_id, sender_id, content)
 (1, 3, 'How are you?'),
 (1, 1, 'Hello, world!'),
 (1, 1, 'Hello, world!');
+
+-- insert files attached to private channel
+INSERT INTO files(url, ws_id, uploader_id, size)
+  VALUES ('/files/1/8ab/d00/a3253d525b37958381ba1cb044d1cad887.jpg', 1, 1, 197689),
+('/files/1/0a0/a9f/2a6772942557ab5355d76af442f8f65e01.txt', 1, 1, 13);
+
+INSERT INTO messages(chat_id, sender_id, content, files)
+  VALUES (2, 1, 'Here are the files', '{/files/1/8ab/d00/a3253d525b37958381ba1cb044d1cad887.jpg,/files/1/0a0/a9f/2a6772942557ab5355d76af442f8f65e01.txt}');
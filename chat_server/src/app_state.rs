@@ -1,33 +1,120 @@
-use std::ops::Deref;
+use std::{collections::HashMap, ops::Deref, sync::Arc};
+
+use arc_swap::ArcSwap;
+use chat_core::{DecodingKey, DecodingKeys, EncodingKey};
 
 use crate::{
-    jwt::{DecodingKey, EncodingKey},
+    config::UploadLimits,
+    mailer::{FileMailer, Mailer},
+    middlewares::{ReloadableLimiter, LIMITED_ROUTES},
+    migration::prepare_schema,
+    signer::UrlSigner,
     Config,
 };
 use anyhow::{Context, Result};
 use sqlx::PgPool;
 
 pub struct AppState {
+    /// the config loaded at startup, the parts swapped on reload have their own fields
     pub(crate) config: Config,
     pub(crate) pool: PgPool,
-    #[allow(dead_code)]
-    pub(crate) dk: DecodingKey,
-    pub(crate) ek: EncodingKey,
+    /// the read replica for listing chats and messages, or `pool` if not configured. A list
+    /// fetched right after a notification may miss the new row while the replica lags behind
+    pub(crate) read_pool: PgPool,
+    pub(crate) dk: DecodingKeys,
+    pub(crate) ek: ArcSwap<EncodingKey>,
+    pub(crate) signer: Option<UrlSigner>,
+    pub(crate) auth_limiter: ReloadableLimiter,
+    /// per user limits of `LIMITED_ROUTES`
+    pub(crate) route_limiters: HashMap<&'static str, Arc<ReloadableLimiter>>,
+    pub(crate) upload_limits: ArcSwap<UploadLimits>,
+    pub(crate) mailer: Box<dyn Mailer>,
 }
 
 impl AppState {
     pub async fn try_new(config: Config) -> Result<Self> {
-        let pool = PgPool::connect(&config.db.url)
+        let pool = config.db.connect().await?;
+        let read_pool = config
+            .db
+            .connect_read(&pool)
             .await
-            .context("connect to db failed")?;
-        let dk = DecodingKey::load(&config.auth.pk).context("load pk failed")?;
+            .context("connect to read replica failed")?;
+        prepare_schema(&pool, &config.migrate).await?;
+        let dk = config.auth.decoding_keys().context("load pk failed")?;
         let ek = EncodingKey::load(&config.auth.sk).context("load sk failed")?;
-        Ok(Self {
+        Ok(Self::with_pools(config, pool, read_pool, dk, ek))
+    }
+
+    /// state of chat_admin, only `db` is needed. The schema is left as it is, like by
+    /// `chat_server migrate`, and no token is signed nor verified, so a throwaway key stands
+    /// in for the configured ones
+    pub async fn try_new_admin(config: Config) -> Result<Self> {
+        let pool = config.db.connect().await?;
+        let read_pool = pool.clone();
+        Ok(Self::with_pools(
             config,
             pool,
-            dk,
-            ek,
-        })
+            read_pool,
+            Vec::new(),
+            EncodingKey::generate(),
+        ))
+    }
+
+    fn with_pools(
+        config: Config,
+        pool: PgPool,
+        read_pool: PgPool,
+        dk: Vec<DecodingKey>,
+        ek: EncodingKey,
+    ) -> Self {
+        let signer = (!config.file.url_secret.is_empty())
+            .then(|| UrlSigner::new(&config.file.url_secret, config.file.url_ttl));
+        let auth_limiter = ReloadableLimiter::new(config.auth.rate_limit.as_ref());
+        let route_limiters = LIMITED_ROUTES
+            .into_iter()
+            .map(|route| {
+                let limiter = ReloadableLimiter::new(config.rate_limit.get(route));
+                (route, Arc::new(limiter))
+            })
+            .collect();
+        let upload_limits = ArcSwap::from_pointee(UploadLimits::from(&config.file));
+        let mailer = Box::new(FileMailer::new(&config.mail));
+        Self {
+            config,
+            pool,
+            read_pool,
+            dk: DecodingKeys::new(dk),
+            ek: ArcSwap::from_pointee(ek),
+            signer,
+            auth_limiter,
+            route_limiters,
+            upload_limits,
+            mailer,
+        }
+    }
+
+    /// swap the keys, rate limits and upload limits of a validated config. Everything is
+    /// loaded before the first swap so a failed reload changes nothing. Other changes, e.g.
+    /// of `listen`, `db` or `file.base_dir`, take effect on restart
+    pub fn reload(&self, config: &Config) -> Result<()> {
+        let dk = config.auth.decoding_keys().context("load pk failed")?;
+        let ek = EncodingKey::load(&config.auth.sk).context("load sk failed")?;
+
+        self.dk.store(dk);
+        self.ek.store(Arc::new(ek));
+        self.auth_limiter.update(config.auth.rate_limit.as_ref());
+        for (route, limiter) in &self.route_limiters {
+            limiter.update(config.rate_limit.get(*route));
+        }
+        self.upload_limits
+            .store(Arc::new(UploadLimits::from(&config.file)));
+        Ok(())
+    }
+
+    /// close the db pools once the checked out connections are returned
+    pub async fn close(&self) {
+        self.pool.close().await;
+        self.read_pool.close().await;
     }
 }
 
@@ -42,10 +129,16 @@ impl Deref for AppState {
 #[cfg(test)]
 mod test {
     use crate::{
-        config::{AuthConfig, DbConfig, FileConfig},
+        config::{
+            AuthConfig, FileConfig, FileTypeConfig, LockoutConfig, MailConfig, RateLimitConfig,
+            ThumbnailConfig,
+        },
+        migration::EmbeddedMigrations,
         AppState, Config,
     };
     use anyhow::Result;
+    use chat_core::{DbConfig, User};
+    use jwt_simple::prelude::*;
     use sqlx::{Pool, Postgres};
     use sqlx_db_tester::TestPg;
     use std::env;
@@ -71,13 +164,41 @@ mod test {
             let decoding_pem = include_str!("../fixtures/decoding.pem");
 
             let config = Config {
-                db: DbConfig { url: tdb.url() },
+                db: DbConfig {
+                    url: tdb.url(),
+                    ..Default::default()
+                },
                 file: FileConfig {
                     base_dir: "/tmp/chat_server".into(),
+                    thumbnails: vec![ThumbnailConfig {
+                        name: "thumb".to_string(),
+                        width: 128,
+                        height: 128,
+                    }],
+                    types: FileTypeConfig {
+                        deny: vec!["text/html".to_string()],
+                        ..Default::default()
+                    },
+                    url_secret: "test url secret".to_string(),
+                    url_ttl: 60,
+                    ..Default::default()
                 },
                 auth: AuthConfig {
                     sk: encoding_pem.to_string(),
                     pk: decoding_pem.to_string(),
+                    lockout: Some(LockoutConfig {
+                        max_failures: 3,
+                        base: 60,
+                        max: 3600,
+                    }),
+                    verify_token_ttl: 60,
+                    reset_token_ttl: 60,
+                    ..Default::default()
+                },
+                mail: MailConfig {
+                    from: "noreply@acme.org".to_string(),
+                    outbox: Some(env::temp_dir().join(format!("outbox-{}.jsonl", tdb.dbname))),
+                    ..Default::default()
                 },
                 ..Default::default()
             };
@@ -87,8 +208,7 @@ mod test {
 
         async fn init_test_db(url: String) -> Result<(TestPg, Pool<Postgres>)> {
             // 创建测试数据库
-            let migrations = std::path::Path::new("../migrations");
-            let tdb = TestPg::new(url, migrations);
+            let tdb = TestPg::new(url, EmbeddedMigrations);
 
             let pool = tdb.get_pool().await;
 
@@ -112,4 +232,39 @@ mod test {
             Ok((tdb, pool))
         }
     }
+
+    #[tokio::test]
+    async fn reload_should_swap_keys_and_limits() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let user = User::new(1, "Tyr Chen", "tchen@acme.org");
+        let old_token = state.ek.load().sign(user.clone())?;
+
+        // rotate the keys, tokens of the old key are still accepted
+        let key_pair = Ed25519KeyPair::generate();
+        let mut config: Config = serde_yaml::from_value(serde_yaml::to_value(&state.config)?)?;
+        config.auth.sk = key_pair.to_pem();
+        config.auth.pk = key_pair.public_key().to_pem();
+        config.auth.accepted_pks = vec![state.auth.pk.clone()];
+        config.auth.rate_limit = Some(RateLimitConfig {
+            burst: 1,
+            per_minute: 1,
+        });
+        config.file.quota = 1024;
+        state.reload(&config)?;
+
+        let new_token = state.ek.load().sign(user.clone())?;
+        assert_eq!(state.dk.verify(&old_token)?, user);
+        assert_eq!(state.dk.verify(&new_token)?, user);
+        assert_eq!(state.auth_limiter.load().unwrap().limit(), 1);
+        assert_eq!(state.upload_limits.load().quota, 1024);
+
+        // a rejected reload keeps the current keys
+        config.auth.sk = "not a pem".to_string();
+        config.auth.accepted_pks.clear();
+        config.file.quota = 0;
+        assert!(state.reload(&config).is_err());
+        assert_eq!(state.dk.verify(&old_token)?, user);
+        assert_eq!(state.upload_limits.load().quota, 1024);
+        Ok(())
+    }
 }
@@ -0,0 +1,155 @@
+use std::{io, time::Duration};
+
+use anyhow::{bail, Result};
+use chat_core::{init_tracing, ConfigErrors};
+use chat_server::{AppState, Config, Workspace};
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+
+/// Operate the data of chat_server, configured like the server by `$CHAT_CONFIG_PATH` and the
+/// `CHAT_*` env. Only `db` is needed, and `file` for `gc`; the schema isn't migrated
+#[derive(Debug, Parser)]
+#[command(name = "chat_admin")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// list, create or transfer workspaces
+    #[command(subcommand)]
+    Workspace(WorkspaceCommand),
+    /// reset the password of, disable or enable a user
+    #[command(subcommand)]
+    User(UserCommand),
+    /// rebuild the indexes of messages, e.g. the gin index of their files
+    Reindex,
+    /// remove the files no message or avatar refers to
+    Gc {
+        /// seconds an unattached file is kept, `file.gc.grace` or a day if not set
+        #[arg(long)]
+        grace: Option<u64>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum WorkspaceCommand {
+    /// print id, name, owner and creation time of all workspaces
+    List,
+    /// create an empty workspace, its first user to sign up becomes the owner
+    Create { name: String },
+    /// hand the workspace over to another user of it
+    Transfer { id: i64, owner_id: i64 },
+}
+
+#[derive(Debug, Subcommand)]
+enum UserCommand {
+    /// set a new password, read from stdin if not given, and revoke the issued tokens and signed
+    /// urls
+    ResetPassword {
+        id: i64,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// block signin and revoke the issued tokens and signed urls, open event streams of
+    /// notify_server last until they reconnect
+    Disable { id: i64 },
+    /// allow a disabled user to sign in again
+    Enable { id: i64 },
+}
+
+const DEFAULT_GC_GRACE: u64 = 60 * 60 * 24;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let cli = Cli::parse();
+
+    // 与服务共用配置和服务方法, 日志只用于连接重试等警告
+    let config = Config::load().await?;
+    let mut errors = ConfigErrors::default();
+    config.db.validate(&mut errors);
+    if matches!(cli.command, Command::Gc { .. }) {
+        config.file.validate(&mut errors).await;
+    }
+    errors.into_result()?;
+    let _telemetry = init_tracing("chat_admin", &config.log, &Default::default())?;
+    let state = AppState::try_new_admin(config).await?;
+
+    let result = run(&state, cli.command).await;
+    state.close().await;
+    result
+}
+
+async fn run(state: &AppState, command: Command) -> Result<()> {
+    match command {
+        Command::Workspace(WorkspaceCommand::List) => {
+            for ws in state.list_workspaces().await? {
+                print_workspace(&ws);
+            }
+        }
+        Command::Workspace(WorkspaceCommand::Create { name }) => {
+            if !(1..=32).contains(&name.chars().count()) {
+                bail!("workspace name must be 1 to 32 characters");
+            }
+            if state.find_workspace_by_name(&name).await?.is_some() {
+                bail!("workspace {name} already exists");
+            }
+            print_workspace(&state.create_workspace(&name, 0).await?);
+        }
+        Command::Workspace(WorkspaceCommand::Transfer { id, owner_id }) => {
+            print_workspace(&state.transfer_workspace(id, owner_id).await?);
+        }
+        Command::User(UserCommand::ResetPassword { id, password }) => {
+            let password = match password {
+                Some(password) => password,
+                None => read_password()?,
+            };
+            if !(6..=128).contains(&password.chars().count()) {
+                bail!("password must be 6 to 128 characters");
+            }
+            state.set_password(id, &password).await?;
+            println!("password of user {id} reset");
+        }
+        Command::User(UserCommand::Disable { id }) => {
+            state.set_user_disabled(id, true).await?;
+            println!("user {id} disabled");
+        }
+        Command::User(UserCommand::Enable { id }) => {
+            state.set_user_disabled(id, false).await?;
+            println!("user {id} enabled");
+        }
+        Command::Reindex => {
+            state.reindex_messages().await?;
+            println!("indexes of messages rebuilt");
+        }
+        Command::Gc { grace } => {
+            let grace = grace
+                .or(state.file.gc.as_ref().map(|gc| gc.grace))
+                .unwrap_or(DEFAULT_GC_GRACE);
+            let urls = state.gc_files(Duration::from_secs(grace)).await?;
+            for url in &urls {
+                println!("removed {url}");
+            }
+            println!("{} files removed", urls.len());
+        }
+    }
+    Ok(())
+}
+
+fn print_workspace(ws: &Workspace) {
+    println!(
+        "{}\t{}\towner {}\tcreated {}",
+        ws.id,
+        ws.name,
+        ws.owner_id,
+        ws.created_at.to_rfc3339()
+    );
+}
+
+fn read_password() -> Result<String> {
+    let mut password = String::new();
+    io::stdin().read_line(&mut password)?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
@@ -1,53 +1,455 @@
-use std::{
-    fmt::Display,
-    path::{Path, PathBuf},
+use std::{collections::HashMap, env, path::PathBuf};
+
+use anyhow::Result;
+use chat_core::{
+    ensure_writable_dir, env_vars, load_config, read_secret, redact_config, ConfigErrors, DbConfig,
+    DecodingKey, DecodingKeys, EncodingKey, ListenConfig, LogConfig, TelemetryConfig, User,
 };
+use serde::{Deserialize, Serialize};
+
+use crate::MigrateConfig;
+
+/// env overrides start with it, e.g. `CHAT_LISTEN__PORT=8080`
+const ENV_PREFIX: &str = "CHAT";
 
-use anyhow::{Context, Result};
-use serde::Deserialize;
-use tokio::{fs::File, io::AsyncReadExt};
+/// signing key and url secret of the tests, refused outside of them
+const FIXTURE_SK: &str = include_str!("../fixtures/encoding.pem");
+const FIXTURE_URL_SECRET: &str = include_str!("../fixtures/url_secret");
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Config {
+    #[serde(default)]
     pub listen: ListenConfig,
+    #[serde(default)]
     pub db: DbConfig,
+    #[serde(default)]
+    pub migrate: MigrateConfig,
+    #[serde(default)]
     pub auth: AuthConfig,
+    #[serde(default)]
     pub file: FileConfig,
+    #[serde(default)]
+    pub mail: MailConfig,
+    /// per user rate limits by route: list_messages, list_chats, create_chat, upload, files
+    #[serde(default)]
+    pub rate_limit: HashMap<String, RateLimitConfig>,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
 }
 
-#[derive(Debug, Default, Deserialize)]
-pub struct ListenConfig {
-    pub host: String,
-    pub port: u16,
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// pem of the token signing key, or read from `sk_file`
+    #[serde(default)]
+    pub sk: String,
+    #[serde(default)]
+    pub sk_file: Option<PathBuf>,
+    /// pem of the token verifying key, or read from `pk_file`
+    #[serde(default)]
+    pub pk: String,
+    #[serde(default)]
+    pub pk_file: Option<PathBuf>,
+    /// pems of previous verifying keys still accepted, e.g. while rotating the keys
+    #[serde(default)]
+    pub accepted_pks: Vec<String>,
+    /// limit signin/signup per ip and per email
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// lock an email out after repeated signin failures
+    #[serde(default)]
+    pub lockout: Option<LockoutConfig>,
+    /// seconds a verify email token is valid
+    #[serde(default = "default_verify_token_ttl")]
+    pub verify_token_ttl: u64,
+    /// seconds a reset password token is valid
+    #[serde(default = "default_reset_token_ttl")]
+    pub reset_token_ttl: u64,
 }
 
-#[derive(Debug, Default, Deserialize)]
-pub struct DbConfig {
-    pub url: String,
+fn default_verify_token_ttl() -> u64 {
+    60 * 60 * 24
 }
 
-#[derive(Debug, Default, Deserialize)]
-pub struct AuthConfig {
-    pub sk: String,
-    pub pk: String,
+fn default_reset_token_ttl() -> u64 {
+    60 * 30
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            sk: String::new(),
+            sk_file: None,
+            pk: String::new(),
+            pk_file: None,
+            accepted_pks: vec![],
+            rate_limit: None,
+            lockout: None,
+            verify_token_ttl: default_verify_token_ttl(),
+            reset_token_ttl: default_reset_token_ttl(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MailConfig {
+    /// sender of outbound mails
+    #[serde(default)]
+    pub from: String,
+    /// file the default mailer appends mails to, mails are only logged if not set
+    #[serde(default)]
+    pub outbox: Option<PathBuf>,
+    /// also log the body of mails without outbox. For development only, bodies carry the
+    /// verify email and reset password tokens
+    #[serde(default)]
+    pub log_body: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// max requests in a burst
+    pub burst: u32,
+    /// requests refilled per minute
+    pub per_minute: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockoutConfig {
+    /// failures allowed before the first lockout
+    pub max_failures: u32,
+    /// seconds of the first lockout, doubled by each further failure
+    pub base: u64,
+    /// max seconds of a lockout
+    pub max: u64,
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct FileConfig {
+    #[serde(default)]
     pub base_dir: PathBuf,
+    /// thumbnail sizes generated for uploaded images
+    #[serde(default)]
+    pub thumbnails: Vec<ThumbnailConfig>,
+    /// mime types accepted by upload
+    #[serde(default)]
+    pub types: FileTypeConfig,
+    /// per workspace overrides of `types`
+    #[serde(default)]
+    pub workspace_types: HashMap<i64, FileTypeConfig>,
+    /// secret of signed file urls, or read from `url_secret_file`. Signing is disabled if empty
+    #[serde(default)]
+    pub url_secret: String,
+    #[serde(default)]
+    pub url_secret_file: Option<PathBuf>,
+    /// lifetime of signed file urls in seconds
+    #[serde(default = "default_url_ttl")]
+    pub url_ttl: u64,
+    /// max bytes stored per workspace, 0 means unlimited
+    #[serde(default)]
+    pub quota: u64,
+    /// orphaned file sweeper, disabled if not configured
+    #[serde(default)]
+    pub gc: Option<FileGcConfig>,
+}
+
+fn default_url_ttl() -> u64 {
+    60 * 60
+}
+
+impl Default for FileConfig {
+    fn default() -> Self {
+        Self {
+            base_dir: PathBuf::new(),
+            thumbnails: vec![],
+            types: FileTypeConfig::default(),
+            workspace_types: HashMap::new(),
+            url_secret: String::new(),
+            url_secret_file: None,
+            url_ttl: default_url_ttl(),
+            quota: 0,
+            gc: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThumbnailConfig {
+    /// size name used in `?size=` of the file url, e.g. thumb
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileGcConfig {
+    /// seconds an unattached file is kept
+    pub grace: u64,
+    /// seconds between two sweeps
+    pub interval: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FileTypeConfig {
+    /// mime types or `type/*` patterns, empty means all types are allowed
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl FileConfig {
+    pub fn thumbnail(&self, name: &str) -> Option<&ThumbnailConfig> {
+        self.thumbnails.iter().find(|t| t.name == name)
+    }
+
+    pub async fn validate(&self, errors: &mut ConfigErrors) {
+        if self.base_dir.as_os_str().is_empty() {
+            errors.add("file.base_dir", "must be set");
+        } else if let Err(e) = ensure_writable_dir(&self.base_dir).await {
+            let msg = format!("{} is not writable: {e}", self.base_dir.display());
+            errors.add("file.base_dir", msg);
+        }
+    }
+}
+
+/// The upload checks of `FileConfig`, swapped on reload.
+#[derive(Debug, Clone, Default)]
+pub struct UploadLimits {
+    pub types: FileTypeConfig,
+    pub workspace_types: HashMap<i64, FileTypeConfig>,
+    pub quota: u64,
+}
+
+impl From<&FileConfig> for UploadLimits {
+    fn from(config: &FileConfig) -> Self {
+        Self {
+            types: config.types.clone(),
+            workspace_types: config.workspace_types.clone(),
+            quota: config.quota,
+        }
+    }
+}
+
+impl UploadLimits {
+    pub fn file_types(&self, ws_id: i64) -> &FileTypeConfig {
+        self.workspace_types.get(&ws_id).unwrap_or(&self.types)
+    }
+}
+
+impl FileTypeConfig {
+    // deny wins over allow
+    pub fn is_allowed(&self, mime: &str) -> bool {
+        if self.deny.iter().any(|p| mime_matches(p, mime)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|p| mime_matches(p, mime))
+    }
+}
+
+fn mime_matches(pattern: &str, mime: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(ty) => mime
+            .split_once('/')
+            .is_some_and(|(t, _)| t.eq_ignore_ascii_case(ty)),
+        None => pattern.eq_ignore_ascii_case(mime),
+    }
 }
 
 impl Config {
-    pub async fn try_new(config_path: impl AsRef<Path> + Display) -> Result<Self> {
-        let mut file = File::open(&config_path)
-            .await
-            .with_context(|| format!("try to open config file from {}", &config_path))?;
-        let mut dst = String::new();
-        file.read_to_string(&mut dst)
-            .await
-            .context("read str from config file failed")?;
-        let config = serde_yaml::from_str(dst.as_str())
-            .context("deserialize str to config object failed")?;
+    /// load and validate the config
+    pub async fn try_new() -> Result<Self> {
+        let config = Self::load().await?;
+        config.validate().await?;
+        Ok(config)
+    }
+
+    /// defaults, then the yaml file of `$CHAT_CONFIG_PATH` if set, then the `CHAT_*` env
+    /// overrides. Keys set by file path are read
+    pub async fn load() -> Result<Self> {
+        let path = Self::path();
+        let mut config: Self = load_config(path.as_deref(), ENV_PREFIX, env_vars()).await?;
+        let auth = &mut config.auth;
+        read_secret(&mut auth.sk, auth.sk_file.as_ref(), "auth.sk_file").await?;
+        read_secret(&mut auth.pk, auth.pk_file.as_ref(), "auth.pk_file").await?;
+        let file = &mut config.file;
+        let url_secret_file = file.url_secret_file.as_ref();
+        read_secret(
+            &mut file.url_secret,
+            url_secret_file,
+            "file.url_secret_file",
+        )
+        .await?;
         Ok(config)
     }
+
+    pub fn path() -> Option<PathBuf> {
+        env::var_os("CHAT_CONFIG_PATH").map(PathBuf::from)
+    }
+
+    /// the yaml file and the key files, a reload is triggered when one of them changes
+    pub fn watched_files(&self) -> Vec<PathBuf> {
+        let files = [
+            Self::path(),
+            self.auth.sk_file.clone(),
+            self.auth.pk_file.clone(),
+        ];
+        files.into_iter().flatten().collect()
+    }
+
+    pub async fn validate(&self) -> Result<()> {
+        let mut errors = ConfigErrors::default();
+        self.listen.validate(&mut errors);
+        self.db.validate(&mut errors);
+        self.auth.validate(&mut errors);
+        self.log.validate(&mut errors);
+        self.file.validate(&mut errors).await;
+        if !cfg!(test) {
+            self.reject_fixture_secrets(&mut errors);
+        }
+        errors.into_result()
+    }
+
+    /// the key and secret under `fixtures/` are committed, anyone could sign with them
+    fn reject_fixture_secrets(&self, errors: &mut ConfigErrors) {
+        let msg = "is the committed test fixture, generate your own";
+        if self.auth.sk.trim() == FIXTURE_SK.trim() {
+            errors.add("auth.sk", msg);
+        }
+        if self.file.url_secret.trim() == FIXTURE_URL_SECRET.trim() {
+            errors.add("file.url_secret", msg);
+        }
+    }
+
+    /// the effective config as yaml with the secrets redacted
+    pub fn redacted(&self) -> Result<String> {
+        redact_config(self, &["auth.sk", "file.url_secret"])
+    }
+}
+
+impl AuthConfig {
+    fn validate(&self, errors: &mut ConfigErrors) {
+        let ek = match EncodingKey::load(&self.sk) {
+            Ok(ek) => Some(ek),
+            Err(_) if self.sk.is_empty() => {
+                errors.add("auth.sk", "must be set, inline or by auth.sk_file");
+                None
+            }
+            Err(e) => {
+                errors.add("auth.sk", format!("invalid PEM: {e}"));
+                None
+            }
+        };
+        let dk = match DecodingKey::load(&self.pk) {
+            Ok(dk) => Some(dk),
+            Err(_) if self.pk.is_empty() => {
+                errors.add("auth.pk", "must be set, inline or by auth.pk_file");
+                None
+            }
+            Err(e) => {
+                errors.add("auth.pk", format!("invalid PEM: {e}"));
+                None
+            }
+        };
+        if let (Some(ek), Some(dk)) = (ek, dk) {
+            let user = User::new(0, "config", "config@localhost");
+            if ek.sign(user).and_then(|token| dk.verify(&token)).is_err() {
+                errors.add("auth.pk", "doesn't match auth.sk");
+            }
+        }
+        for (i, pem) in self.accepted_pks.iter().enumerate() {
+            if let Err(e) = DecodingKey::load(pem) {
+                errors.add(
+                    &format!("auth.accepted_pks[{i}]"),
+                    format!("invalid PEM: {e}"),
+                );
+            }
+        }
+    }
+
+    /// the verifying key of `sk` first, then the previous ones
+    pub fn decoding_keys(&self) -> Result<Vec<DecodingKey>> {
+        let pems = std::iter::once(&self.pk).chain(&self.accepted_pks);
+        DecodingKeys::load_all(pems.map(String::as_str))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_type_config_should_work() {
+        let types = FileTypeConfig {
+            allow: vec!["image/*".to_string(), "text/plain".to_string()],
+            deny: vec!["image/svg+xml".to_string()],
+        };
+        assert!(types.is_allowed("image/png"));
+        assert!(types.is_allowed("text/plain"));
+        assert!(!types.is_allowed("image/svg+xml"));
+        assert!(!types.is_allowed("text/html"));
+
+        let types = FileTypeConfig::default();
+        assert!(types.is_allowed("application/octet-stream"));
+    }
+
+    #[tokio::test]
+    async fn validate_should_report_all_errors() {
+        let config = Config {
+            db: DbConfig {
+                url: "postgres://localhost/chat".to_string(),
+                ..Default::default()
+            },
+            auth: AuthConfig {
+                sk: "not a pem".to_string(),
+                pk: include_str!("../fixtures/decoding.pem").to_string(),
+                ..Default::default()
+            },
+            file: FileConfig {
+                // a directory can't be created under a file
+                base_dir: PathBuf::from("Cargo.toml/files"),
+                ..Default::default()
+            },
+            log: LogConfig {
+                filter: "chat_server=loud".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let err = config.validate().await.unwrap_err().to_string();
+        assert!(err.contains("listen.port: must be set"));
+        assert!(err.contains("auth.sk: invalid PEM"));
+        assert!(err.contains("file.base_dir: Cargo.toml/files is not writable"));
+        assert!(err.contains("log.filter"));
+        assert!(!err.contains("db.url"));
+    }
+
+    #[test]
+    fn reject_fixture_secrets_should_work() {
+        let mut config = Config::default();
+        config.auth.sk = FIXTURE_SK.to_string();
+        config.file.url_secret = format!("{FIXTURE_URL_SECRET}\n");
+        let mut errors = ConfigErrors::default();
+        config.reject_fixture_secrets(&mut errors);
+        let err = errors.into_result().unwrap_err().to_string();
+        assert!(err.contains("auth.sk: is the committed test fixture"));
+        assert!(err.contains("file.url_secret: is the committed test fixture"));
+
+        config.auth.sk = "a key of my own".to_string();
+        config.file.url_secret = "a secret of my own".to_string();
+        let mut errors = ConfigErrors::default();
+        config.reject_fixture_secrets(&mut errors);
+        assert!(errors.into_result().is_ok());
+    }
+
+    #[test]
+    fn redacted_should_hide_keys_and_secrets() -> Result<()> {
+        let mut config = Config::default();
+        config.auth.sk = include_str!("../fixtures/encoding.pem").to_string();
+        config.file.url_secret = "url secret".to_string();
+        let yaml = config.redacted()?;
+        assert!(!yaml.contains("PRIVATE KEY"));
+        assert!(!yaml.contains("url secret"));
+        Ok(())
+    }
 }
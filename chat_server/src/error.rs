@@ -1,9 +1,20 @@
-use std::io;
-
-use axum::{extract::multipart::MultipartError, http::StatusCode, response::IntoResponse, Json};
-use hyper::header::InvalidHeaderValue;
-use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, io};
+
+use axum::{
+    extract::{
+        multipart::MultipartError,
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+    },
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use hyper::header::{InvalidHeaderValue, RETRY_AFTER};
+use serde_json::{json, Value};
 use thiserror::Error;
+use tracing::error;
+
+pub use chat_core::ErrorOutput;
 
 #[derive(Error, Debug)]
 pub enum AppError {
@@ -13,6 +24,9 @@ pub enum AppError {
     #[error("email already exists: {0}")]
     EmailAlreadyExists(String),
 
+    #[error("user not found: {0}")]
+    UserNotFound(i64),
+
     #[error("file not found: {0}")]
     FileNotFound(String),
 
@@ -40,19 +54,86 @@ pub enum AppError {
     #[error("chat file error: {0}")]
     ChatFileError(String),
 
+    #[error("file type not allowed: {0}")]
+    FileTypeNotAllowed(String),
+
+    #[error("storage quota exceeded: {0}")]
+    StorageQuotaExceeded(String),
+
+    #[error("too many requests, retry after {0} seconds")]
+    TooManyRequests(u64),
+
+    #[error("payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    #[error("invalid token: {0}")]
+    InvalidToken(String),
+
+    #[error("unauthenticated: {0}")]
+    Unauthenticated(String),
+
+    #[error("token of user {0} has been revoked")]
+    TokenRevoked(i64),
+
     #[error("verify chat error: {0}")]
     VerifyChat(String),
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ErrorOutput {
-    pub error: String,
+    #[error("workspace not found: {0}")]
+    WorkspaceNotFound(i64),
+
+    #[error("invalid workspace owner: {0}")]
+    InvalidWorkspaceOwner(String),
+
+    #[error("invalid input: {0}")]
+    ValidationError(#[from] validator::ValidationErrors),
+
+    #[error("invalid body: {0}")]
+    InvalidBody(#[from] JsonRejection),
+
+    #[error("invalid query: {0}")]
+    InvalidQuery(#[from] QueryRejection),
+
+    #[error("invalid path: {0}")]
+    InvalidPath(#[from] PathRejection),
 }
 
-impl ErrorOutput {
-    pub fn new(error: impl Into<String>) -> Self {
-        Self {
-            error: error.into(),
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::EmailNotFound(_) => "email_not_found",
+            AppError::EmailAlreadyExists(_) => "email_already_exists",
+            AppError::UserNotFound(_) => "user_not_found",
+            AppError::FileNotFound(_) => "file_not_found",
+            AppError::IoError(_) | AppError::InvalidHeaderValue(_) | AppError::SqlxError(_) => {
+                "internal_error"
+            }
+            AppError::MultipartError(_) => "invalid_multipart",
+            AppError::PasswordHashError(_) => "password_hash_error",
+            AppError::SignError(_) => "jwt_error",
+            AppError::CreateChatError(_) => "create_chat_error",
+            AppError::ChatFileError(_) => "invalid_file",
+            AppError::FileTypeNotAllowed(_) => "file_type_not_allowed",
+            AppError::StorageQuotaExceeded(_) => "storage_quota_exceeded",
+            AppError::TooManyRequests(_) => "too_many_requests",
+            AppError::PayloadTooLarge(_) => "payload_too_large",
+            AppError::InvalidToken(_) => "invalid_token",
+            AppError::Unauthenticated(_) => "unauthenticated",
+            AppError::TokenRevoked(_) => "token_revoked",
+            AppError::VerifyChat(_) => "not_chat_member",
+            AppError::WorkspaceNotFound(_) => "workspace_not_found",
+            AppError::InvalidWorkspaceOwner(_) => "invalid_workspace_owner",
+            AppError::ValidationError(_) => "validation_failed",
+            AppError::InvalidBody(_) => "invalid_body",
+            AppError::InvalidQuery(_) => "invalid_query",
+            AppError::InvalidPath(_) => "invalid_path",
+        }
+    }
+
+    fn details(&self) -> Option<Value> {
+        match self {
+            AppError::TooManyRequests(secs) => Some(json!({ "retry_after": secs })),
+            AppError::ValidationError(e) => Some(json!({ "fields": field_errors(e) })),
+            _ => None,
         }
     }
 }
@@ -62,17 +143,105 @@ impl IntoResponse for AppError {
         let status = match self {
             AppError::EmailNotFound(_) => StatusCode::NOT_FOUND,
             AppError::EmailAlreadyExists(_) => StatusCode::CONFLICT,
+            AppError::UserNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::FileNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::ChatFileError(_) => StatusCode::BAD_REQUEST,
+            AppError::MultipartError(_) => StatusCode::BAD_REQUEST,
             AppError::PasswordHashError(_) => StatusCode::FORBIDDEN,
             AppError::SignError(_) => StatusCode::FORBIDDEN,
             AppError::CreateChatError(_) => StatusCode::BAD_REQUEST,
             AppError::VerifyChat(_) => StatusCode::FORBIDDEN,
+            AppError::WorkspaceNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::InvalidWorkspaceOwner(_) => StatusCode::BAD_REQUEST,
+            AppError::FileTypeNotAllowed(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::StorageQuotaExceeded(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::InvalidToken(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthenticated(_) => StatusCode::FORBIDDEN,
+            AppError::TokenRevoked(_) => StatusCode::FORBIDDEN,
+            AppError::ValidationError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            // e.g. 415 for a wrong content type, 400 or 422 for a malformed body
+            AppError::InvalidBody(ref e) => e.status(),
+            AppError::InvalidQuery(ref e) => e.status(),
+            AppError::InvalidPath(ref e) => e.status(),
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
+        let retry_after = match self {
+            AppError::TooManyRequests(secs) => Some(secs),
+            _ => None,
+        };
+
+        // internal errors are logged, database or io messages must not reach clients
+        let message = if status.is_server_error() {
+            error!("internal error: {self}");
+            "internal server error".to_string()
+        } else {
+            self.to_string()
+        };
+
         let err_output = ErrorOutput {
-            error: self.to_string(),
+            details: self.details(),
+            ..ErrorOutput::new(self.code(), message)
         };
 
-        (status, Json(err_output)).into_response()
+        let mut res = (status, Json(err_output)).into_response();
+        if let Some(secs) = retry_after {
+            res.headers_mut().insert(RETRY_AFTER, secs.into());
+        }
+        res
+    }
+}
+
+fn field_errors(errors: &validator::ValidationErrors) -> BTreeMap<String, Vec<String>> {
+    errors
+        .field_errors()
+        .into_iter()
+        .map(|(field, errors)| {
+            let messages = errors
+                .iter()
+                .map(|e| match &e.message {
+                    Some(message) => message.to_string(),
+                    None => e.code.to_string(),
+                })
+                .collect();
+            (field.to_string(), messages)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    async fn output(err: AppError) -> Result<(StatusCode, ErrorOutput)> {
+        let res = err.into_response();
+        let status = res.status();
+        let body = res.into_body().collect().await?.to_bytes();
+        Ok((status, serde_json::from_slice(&body)?))
+    }
+
+    #[tokio::test]
+    async fn internal_error_should_be_sanitized() -> Result<()> {
+        let err = sqlx::Error::Protocol("relation \"users\" does not exist".to_string());
+        let (status, ret) = output(err.into()).await?;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(ret.code, "internal_error");
+        assert_eq!(ret.error, "internal server error");
+        assert!(ret.details.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn error_output_should_carry_code_and_details() -> Result<()> {
+        let (status, ret) = output(AppError::TooManyRequests(30)).await?;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(ret.code, "too_many_requests");
+        assert_eq!(ret.details, Some(json!({ "retry_after": 30 })));
+        Ok(())
     }
 }
@@ -2,37 +2,138 @@ use std::sync::Arc;
 
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utoipa::ToSchema;
 
-use crate::{services::SigninUser, AppError, AppState, CreateUser, ErrorOutput};
+use super::ValidatedJson;
+use crate::{
+    middlewares::{too_many_requests, ClientIp},
+    services::{ForgotPassword, ResetPassword, SigninUser, VerifyEmail},
+    AppError, AppState, CreateUser, ErrorOutput,
+};
 
+#[utoipa::path(
+    post,
+    path = "/api/signup",
+    tag = "auth",
+    request_body = CreateUser,
+    responses(
+        (status = 201, description = "User created", body = AuthOutput),
+        (status = 409, description = "Email already exists", body = ErrorOutput),
+        (status = 422, description = "Invalid fields", body = ErrorOutput),
+    )
+)]
 pub(crate) async fn signup_handler(
     State(state): State<Arc<AppState>>,
-    Json(input): Json<CreateUser>,
+    ValidatedJson(input): ValidatedJson<CreateUser>,
 ) -> Result<impl IntoResponse, AppError> {
     let user = state.create_user(&input).await?;
-    let token = state.ek.sign(user)?;
+    // the user is created already, a mail failure must not fail the signup
+    if let Err(e) = state.send_verify_email(&user).await {
+        warn!(user_id = user.id, "send verify email failed: {e}");
+    }
+    let token = state.ek.load().sign(user)?;
     let body = Json(AuthOutput { token });
     Ok((StatusCode::CREATED, body))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/signin",
+    tag = "auth",
+    request_body = SigninUser,
+    responses(
+        (status = 200, description = "User signed in", body = AuthOutput),
+        (status = 403, description = "Invalid email or password", body = ErrorOutput),
+        (status = 422, description = "Invalid fields", body = ErrorOutput),
+        (status = 429, description = "Locked out after repeated failures", body = ErrorOutput),
+    )
+)]
 pub(crate) async fn signin_handler(
     State(state): State<Arc<AppState>>,
-    Json(input): Json<SigninUser>,
+    ClientIp(ip): ClientIp,
+    ValidatedJson(input): ValidatedJson<SigninUser>,
 ) -> Result<impl IntoResponse, AppError> {
+    if let Some(retry_after) = state.signin_lockout(&input.email).await? {
+        return Ok(too_many_requests(retry_after));
+    }
+
     let user = state.verify_user(&input).await?;
+    state
+        .add_signin_attempt(&input.email, &ip, user.is_some())
+        .await?;
     match user {
         Some(user) => {
-            let token = state.ek.sign(user)?;
+            let token = state.ek.load().sign(user)?;
             Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
         }
         None => {
-            let body = Json(ErrorOutput::new("Invalid email or password"));
+            let body = Json(ErrorOutput::new(
+                "invalid_credentials",
+                "Invalid email or password",
+            ));
             Ok((StatusCode::FORBIDDEN, body).into_response())
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[utoipa::path(
+    post,
+    path = "/api/email/verify",
+    tag = "auth",
+    request_body = VerifyEmail,
+    responses(
+        (status = 204, description = "Email verified"),
+        (status = 400, description = "Invalid or expired token", body = ErrorOutput),
+        (status = 422, description = "Invalid fields", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn verify_email_handler(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(input): ValidatedJson<VerifyEmail>,
+) -> Result<impl IntoResponse, AppError> {
+    state.verify_email(&input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/password/forgot",
+    tag = "auth",
+    request_body = ForgotPassword,
+    responses(
+        (status = 202, description = "Reset token mailed if the email exists"),
+        (status = 422, description = "Invalid fields", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn forgot_password_handler(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(input): ValidatedJson<ForgotPassword>,
+) -> Result<impl IntoResponse, AppError> {
+    state.forgot_password(&input).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/password/reset",
+    tag = "auth",
+    request_body = ResetPassword,
+    responses(
+        (status = 204, description = "Password reset"),
+        (status = 400, description = "Invalid or expired token", body = ErrorOutput),
+        (status = 422, description = "Invalid fields", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn reset_password_handler(
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(input): ValidatedJson<ResetPassword>,
+) -> Result<impl IntoResponse, AppError> {
+    state.reset_password(&input).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct AuthOutput {
     pub token: String,
 }
@@ -42,20 +143,36 @@ mod test {
     use std::sync::Arc;
 
     use anyhow::Result;
-    use axum::{body::to_bytes, extract::State, http::StatusCode, response::IntoResponse, Json};
+    use axum::{body::to_bytes, extract::State, http::StatusCode, response::IntoResponse};
+    use hyper::header::RETRY_AFTER;
     use jwt_simple::reexports::serde_json;
 
     use crate::{
-        handlers::auth::{signin_handler, signup_handler, AuthOutput},
-        services::SigninUser,
+        handlers::auth::{
+            forgot_password_handler, reset_password_handler, signin_handler, signup_handler,
+            verify_email_handler, AuthOutput,
+        },
+        handlers::ValidatedJson,
+        middlewares::ClientIp,
+        services::{ForgotPassword, ResetPassword, SigninUser, VerifyEmail},
         AppState, CreateUser, ErrorOutput,
     };
 
+    /// token of the last mail in the test outbox, it is the last line of the body
+    async fn last_mailed_token(state: &AppState) -> Result<String> {
+        let outbox = state.mail.outbox.as_ref().expect("outbox should be set");
+        let content = tokio::fs::read_to_string(outbox).await?;
+        let mail: serde_json::Value = serde_json::from_str(content.lines().last().unwrap())?;
+        let body = mail["body"].as_str().unwrap();
+        let token = body.lines().rev().find(|l| !l.is_empty()).unwrap();
+        Ok(token.to_string())
+    }
+
     #[tokio::test]
     async fn signup_should_work() -> Result<()> {
         let (_tdb, state) = AppState::try_new_test().await?;
         let input = CreateUser::new("acme", "Tian Chen", "tyr@acme.org", "123456");
-        let ret = signup_handler(State(Arc::new(state)), Json(input))
+        let ret = signup_handler(State(Arc::new(state)), ValidatedJson(input))
             .await?
             .into_response();
         assert_eq!(ret.status(), StatusCode::CREATED);
@@ -72,7 +189,7 @@ mod test {
         let (_tdb, state) = AppState::try_new_test().await?;
         let input = CreateUser::new("acme", "Tyr Chen", "tchen@acme.org", "123456");
 
-        let ret = signup_handler(State(Arc::new(state)), Json(input))
+        let ret = signup_handler(State(Arc::new(state)), ValidatedJson(input))
             .await
             .into_response();
         assert_eq!(ret.status(), StatusCode::CONFLICT);
@@ -81,6 +198,7 @@ mod test {
         // let body = body.collect().await?.to_bytes();
         let ret: ErrorOutput = serde_json::from_slice(&body)?;
 
+        assert_eq!(ret.code, "email_already_exists");
         assert_eq!(ret.error, "email already exists: tchen@acme.org");
         Ok(())
     }
@@ -89,7 +207,8 @@ mod test {
     async fn signin_should_work() -> Result<()> {
         let (_tdb, state) = AppState::try_new_test().await?;
         let input = SigninUser::new("tchen@acme.org", "123456");
-        let ret = signin_handler(State(Arc::new(state)), Json(input))
+        let ip = ClientIp("127.0.0.1".to_string());
+        let ret = signin_handler(State(Arc::new(state)), ip, ValidatedJson(input))
             .await?
             .into_response();
         assert_eq!(ret.status(), StatusCode::OK);
@@ -100,4 +219,101 @@ mod test {
         assert_ne!(ret.token, "");
         Ok(())
     }
+
+    #[tokio::test]
+    async fn signup_should_mail_verify_token() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let state = Arc::new(state);
+        let input = CreateUser::new("acme", "Tian Chen", "tyr@acme.org", "123456");
+        signup_handler(State(state.clone()), ValidatedJson(input)).await?;
+
+        let token = last_mailed_token(&state).await?;
+        let ret = verify_email_handler(State(state.clone()), ValidatedJson(VerifyEmail { token }))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+
+        let user = state.find_user_by_email("tyr@acme.org").await?.unwrap();
+        let (verified,): (bool,) =
+            sqlx::query_as("SELECT verified_at IS NOT NULL FROM users WHERE id = $1")
+                .bind(user.id)
+                .fetch_one(&state.pool)
+                .await?;
+        assert!(verified);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reset_password_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let state = Arc::new(state);
+        let input = ForgotPassword {
+            email: "tchen@acme.org".to_string(),
+        };
+        let ret = forgot_password_handler(State(state.clone()), ValidatedJson(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::ACCEPTED);
+
+        let input = ResetPassword {
+            token: last_mailed_token(&state).await?,
+            password: "new password".to_string(),
+        };
+        let ret = reset_password_handler(State(state.clone()), ValidatedJson(input.clone()))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NO_CONTENT);
+
+        let signin = SigninUser::new("tchen@acme.org", "new password");
+        let ip = ClientIp("127.0.0.1".to_string());
+        let ret = signin_handler(State(state.clone()), ip, ValidatedJson(signin))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+
+        // token is single use
+        let ret = reset_password_handler(State(state), ValidatedJson(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forgot_password_should_ignore_unknown_email() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let input = ForgotPassword {
+            email: "nobody@acme.org".to_string(),
+        };
+        let ret = forgot_password_handler(State(Arc::new(state)), ValidatedJson(input))
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::ACCEPTED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signin_should_lock_out_after_failures() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let state = Arc::new(state);
+        let max_failures = state.auth.lockout.as_ref().unwrap().max_failures;
+
+        let signin = |password: &str| {
+            let input = SigninUser::new("tchen@acme.org", password);
+            let ip = ClientIp("127.0.0.1".to_string());
+            signin_handler(State(state.clone()), ip, ValidatedJson(input))
+        };
+
+        for _ in 0..max_failures {
+            let ret = signin("bad password").await.into_response();
+            assert_eq!(ret.status(), StatusCode::FORBIDDEN);
+        }
+
+        // even the right password is rejected while locked out
+        let ret = signin("123456").await.into_response();
+        assert_eq!(ret.status(), StatusCode::TOO_MANY_REQUESTS);
+        // a bit less than the 60s of the lockout is left, rounded up
+        assert_eq!(ret.headers()[RETRY_AFTER], "60");
+        Ok(())
+    }
 }
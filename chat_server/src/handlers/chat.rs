@@ -4,11 +4,21 @@ use axum::{extract::State, response::IntoResponse, Extension, Json};
 use hyper::StatusCode;
 use serde_json::json;
 
+use super::ValidatedJson;
 use crate::{
     services::{CreateChat, User},
     AppError, AppState,
 };
 
+#[utoipa::path(
+    get,
+    path = "/api/chats",
+    tag = "chat",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "Chats of the workspace", body = Vec<Chat>),
+    )
+)]
 pub(crate) async fn list_chats_handler(
     Extension(user): Extension<User>,
     State(state): State<Arc<AppState>>,
@@ -17,10 +27,22 @@ pub(crate) async fn list_chats_handler(
     Ok((StatusCode::OK, Json(chat)))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/chats",
+    tag = "chat",
+    security(("token" = [])),
+    request_body = CreateChat,
+    responses(
+        (status = 201, description = "Id of the created chat", body = Object, example = json!({"chat_id": 1})),
+        (status = 400, description = "Invalid members", body = ErrorOutput),
+        (status = 422, description = "Invalid fields", body = ErrorOutput),
+    )
+)]
 pub(crate) async fn create_chat_handler(
     Extension(user): Extension<User>,
     State(state): State<Arc<AppState>>,
-    Json(input): Json<CreateChat>,
+    ValidatedJson(input): ValidatedJson<CreateChat>,
 ) -> Result<impl IntoResponse, AppError> {
     let chat_id = state.create_chat(&input, user.ws_id).await?;
 
@@ -37,13 +59,13 @@ mod test {
     use std::{collections::HashSet, sync::Arc};
 
     use anyhow::Result;
-    use axum::{extract::State, response::IntoResponse, Extension, Json};
+    use axum::{extract::State, response::IntoResponse, Extension};
     use http_body_util::BodyExt;
     use hyper::StatusCode;
     use serde_json::Value;
 
     use crate::{
-        handlers::chat::create_chat_handler,
+        handlers::{chat::create_chat_handler, ValidatedJson},
         services::{CreateChat, User},
         AppState, ErrorOutput,
     };
@@ -56,9 +78,13 @@ mod test {
             ..Default::default()
         };
         let input = CreateChat::new(None, HashSet::from([1, 2, 3]), true);
-        let ret = create_chat_handler(Extension(user), State(Arc::new(state)), Json(input))
-            .await?
-            .into_response();
+        let ret = create_chat_handler(
+            Extension(user),
+            State(Arc::new(state)),
+            ValidatedJson(input),
+        )
+        .await?
+        .into_response();
         assert_eq!(ret.status(), StatusCode::CREATED);
         // let body = ret.into_body();
         // let body = to_bytes(body, usize::MAX).await?;
@@ -78,12 +104,18 @@ mod test {
             ..Default::default()
         };
         let input = CreateChat::new(None, HashSet::from([1, 2, 99]), true);
-        let ret = create_chat_handler(Extension(user), State(Arc::new(state)), Json(input)).await;
+        let ret = create_chat_handler(
+            Extension(user),
+            State(Arc::new(state)),
+            ValidatedJson(input),
+        )
+        .await;
         let ret = ret.into_response();
         assert_eq!(ret.status(), StatusCode::BAD_REQUEST);
 
         let body = ret.into_body().collect().await?.to_bytes();
         let ret: ErrorOutput = serde_json::from_slice(&body)?;
+        assert_eq!(ret.code, "create_chat_error");
         assert_eq!(
             ret.error,
             "create chat error: the chat members ([99]) is not exist"
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use axum::extract::State;
+use chat_core::{check, ensure_writable_dir, HealthOutput, User};
+
+use crate::AppState;
+
+/// liveness, the process is up and serving requests
+pub(crate) async fn healthz_handler() -> HealthOutput {
+    HealthOutput::new(vec![])
+}
+
+/// readiness, the dependencies needed to serve the api are usable
+pub(crate) async fn readyz_handler(State(state): State<Arc<AppState>>) -> HealthOutput {
+    let (db, storage, keys) = tokio::join!(
+        check("db", check_db(&state)),
+        check("storage", check_storage(&state)),
+        check("keys", async { check_keys(&state) }),
+    );
+    HealthOutput::new(vec![db, storage, keys])
+}
+
+/// the primary and the read replica if any
+async fn check_db(state: &AppState) -> Result<()> {
+    sqlx::query("SELECT 1").execute(&state.pool).await?;
+    sqlx::query("SELECT 1").execute(&state.read_pool).await?;
+    Ok(())
+}
+
+/// write and remove a probe file under `file.base_dir`
+async fn check_storage(state: &AppState) -> Result<()> {
+    ensure_writable_dir(&state.file.base_dir).await?;
+    Ok(())
+}
+
+/// the keys loaded at startup can sign and verify a token
+fn check_keys(state: &AppState) -> Result<()> {
+    let token = state
+        .ek
+        .load()
+        .sign(User::new(0, "readyz", "readyz@localhost"))?;
+    state.dk.verify(&token)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, response::IntoResponse};
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn readyz_should_check_dependencies() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let res = readyz_handler(State(Arc::new(state))).await.into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body = res.into_body().collect().await?.to_bytes();
+        let output: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(output["status"], "ok");
+        let names: Vec<_> = output["checks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["db", "storage", "keys"]);
+        Ok(())
+    }
+}
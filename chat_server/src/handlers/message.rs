@@ -5,30 +5,92 @@ use axum::{
     response::IntoResponse,
     Extension, Json,
 };
-use hyper::{HeaderMap, StatusCode};
-use tokio::fs;
+use hyper::{
+    header::{HeaderValue, CONTENT_DISPOSITION, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
+    HeaderMap, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, task};
 use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
 
+use super::{ValidatedJson, ValidatedQuery};
 use crate::{
     services::{ListMessages, User},
     AppError, AppState,
 };
 
-use super::model::ChatFile;
+use super::model::{generate_thumbnails, is_inline_mime, ChatFile};
+
+pub use chat_core::{SignedFile, UploadOutput};
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FileQuery {
+    /// thumbnail size name configured in `file.thumbnails`
+    pub size: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate, ToSchema)]
+pub struct SignFiles {
+    /// urls of files in messages or avatars, e.g. `/files/1/0a0/a9f/2a67...01.txt`
+    #[validate(length(min = 1, max = 100, message = "must have 1 to 100 urls"))]
+    pub urls: Vec<String>,
+}
+
+/// multipart form of `upload_handler`, only used by the api doc
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub(crate) struct UploadForm {
+    /// files to upload, every field of the form is stored as a file
+    #[schema(value_type = Vec<String>, format = Binary)]
+    file: Vec<Vec<u8>>,
+}
 
+#[utoipa::path(
+    get,
+    path = "/api/{id}/messages",
+    tag = "message",
+    security(("token" = [])),
+    params(("id" = u64, Path, description = "Chat id"), ListMessages),
+    responses(
+        (status = 200, description = "Messages before `last_id`, newest first", body = Vec<Message>),
+        (status = 403, description = "Not a member of the chat", body = ErrorOutput),
+        (status = 422, description = "Invalid fields", body = ErrorOutput),
+    )
+)]
 pub(crate) async fn list_message_handler(
     State(state): State<Arc<AppState>>,
     Path(id): Path<u64>,
-    Query(input): Query<ListMessages>,
+    ValidatedQuery(input): ValidatedQuery<ListMessages>,
 ) -> Result<impl IntoResponse, AppError> {
     let messages = state.list_messages(input, id).await?;
     Ok(Json(messages))
 }
 
+/// accepts a bearer token or the query of a signed url
+#[utoipa::path(
+    get,
+    path = "/api/files/{ws_id}/{path}",
+    tag = "file",
+    security(("token" = []), ()),
+    params(
+        ("ws_id" = i64, Path, description = "Workspace id"),
+        ("path" = String, Path, description = "Path of the file in the workspace"),
+        FileQuery,
+    ),
+    responses(
+        (status = 200, description = "Content of the file or its thumbnail"),
+        (status = 400, description = "Invalid file path", body = ErrorOutput),
+        (status = 404, description = "File not found", body = ErrorOutput),
+    )
+)]
 pub(crate) async fn file_handler(
     Extension(user): Extension<User>,
     State(state): State<Arc<AppState>>,
     Path((ws_id, path)): Path<(i64, String)>,
+    Query(query): Query<FileQuery>,
 ) -> Result<impl IntoResponse, AppError> {
     if user.ws_id != ws_id {
         return Err(AppError::FileNotFound(
@@ -36,20 +98,58 @@ pub(crate) async fn file_handler(
         ));
     }
 
+    // never join the raw path, rebuild it from the validated chat file
+    let chat_file: ChatFile = format!("/files/{ws_id}/{path}").parse()?;
+    let not_found = || AppError::FileNotFound("file doesn't exist".to_string());
+    if !state.can_access_file(&chat_file.url(), user.id).await? {
+        return Err(not_found());
+    }
+
+    let mut path = chat_file.path(&state.file.base_dir);
+    if let Some(size) = query.size {
+        if state.file.thumbnail(&size).is_none() {
+            return Err(AppError::ChatFileError(format!(
+                "thumbnail size {size} is not configured"
+            )));
+        }
+        let thumbnail_path = chat_file.thumbnail_path(&state.file.base_dir, &size);
+        // small images and non-images have no thumbnail, serve the original file
+        if thumbnail_path.exists() {
+            path = thumbnail_path;
+        }
+    }
+
     let base_dir = state.file.base_dir.join(ws_id.to_string());
-    let path = base_dir.join(path);
-    if !path.exists() {
-        return Err(AppError::FileNotFound("file doesn't exist".to_string()));
+    let base_dir = fs::canonicalize(&base_dir).await.map_err(|_| not_found())?;
+    let path = fs::canonicalize(&path).await.map_err(|_| not_found())?;
+    if !path.starts_with(&base_dir) || !path.is_file() {
+        return Err(not_found());
     }
 
     let mime = mime_guess::from_path(&path).first_or_octet_stream();
     let mut headers = HeaderMap::new();
-    headers.insert("content-type", mime.to_string().parse().unwrap());
+    headers.insert(CONTENT_TYPE, mime.to_string().parse()?);
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    if !is_inline_mime(&mime) {
+        headers.insert(CONTENT_DISPOSITION, HeaderValue::from_static("attachment"));
+    }
 
     let body = fs::read(&path).await?;
     Ok((headers, body))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/upload",
+    tag = "file",
+    security(("token" = [])),
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Urls of the uploaded files", body = Vec<UploadOutput>),
+        (status = 413, description = "Workspace storage quota exceeded", body = ErrorOutput),
+        (status = 415, description = "File type not allowed", body = ErrorOutput),
+    )
+)]
 pub(crate) async fn upload_handler(
     Extension(user): Extension<User>,
     State(state): State<Arc<AppState>>,
@@ -57,8 +157,9 @@ pub(crate) async fn upload_handler(
 ) -> Result<impl IntoResponse, AppError> {
     let ws_id = user.ws_id;
     let base_dir = &state.file.base_dir;
+    let limits = state.upload_limits.load();
 
-    let mut urls = Vec::new();
+    let mut files = Vec::new();
     while let Some(field) = multipart.next_field().await? {
         // let Some(name) = field.name() else {
         //     warn!("multipart field name is not exist");
@@ -80,17 +181,92 @@ pub(crate) async fn upload_handler(
         };
 
         let chat_file = ChatFile::new(ws_id, filename.as_str(), &data);
-        let path = chat_file.path(base_dir);
-        if path.exists() {
-            warn!("File {} already exists: {:?}", filename, path);
-        } else {
-            fs::create_dir_all(path.parent().expect("file path parent should exists")).await?;
-            fs::write(path, data).await?;
+        let mime = chat_file.mime();
+        if !limits.file_types(ws_id).is_allowed(mime.essence_str()) {
+            return Err(AppError::FileTypeNotAllowed(format!("{filename} ({mime})")));
         }
-        urls.push(chat_file.url())
+
+        // a file stored already doesn't take more space
+        let quota = limits.quota;
+        if quota > 0 && !chat_file.path(base_dir).exists() {
+            let used = state.workspace_storage(ws_id).await? as u64;
+            if used + data.len() as u64 > quota {
+                return Err(AppError::StorageQuotaExceeded(format!(
+                    "workspace {ws_id} uses {used} of {quota} bytes, {filename} needs {}",
+                    data.len()
+                )));
+            }
+        }
+        state.store_file(&chat_file, user.id, &data).await?;
+        metrics::counter!("upload_bytes_total").increment(data.len() as u64);
+
+        let size = {
+            let chat_file = chat_file.clone();
+            let base_dir = base_dir.clone();
+            let thumbnails = state.file.thumbnails.clone();
+            task::spawn_blocking(move || {
+                generate_thumbnails(&chat_file, &data, &base_dir, &thumbnails)
+            })
+            .await
+        };
+        let size = size.unwrap_or_else(|e| {
+            warn!(ws_id, filename, "failed to generate thumbnails: {e}");
+            None
+        });
+
+        files.push(UploadOutput {
+            url: chat_file.url(),
+            signed_url: state
+                .signer
+                .as_ref()
+                .map(|signer| chat_file.signed_url(signer, &user)),
+            width: size.map(|(width, _)| width),
+            height: size.map(|(_, height)| height),
+        })
     }
 
-    Ok((StatusCode::OK, Json(urls)))
+    Ok((StatusCode::OK, Json(files)))
+}
+
+/// sign the urls of files listed in messages or avatars, e.g. again once the signed url expired
+#[utoipa::path(
+    post,
+    path = "/api/files/sign",
+    tag = "file",
+    security(("token" = [])),
+    request_body = SignFiles,
+    responses(
+        (status = 200, description = "Signed urls in the order of the input", body = Vec<SignedFile>),
+        (status = 400, description = "Invalid file url or signing disabled", body = ErrorOutput),
+        (status = 404, description = "File not found or not readable", body = ErrorOutput),
+        (status = 422, description = "Invalid fields", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn sign_files_handler(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(input): ValidatedJson<SignFiles>,
+) -> Result<impl IntoResponse, AppError> {
+    let Some(signer) = state.signer.as_ref() else {
+        return Err(AppError::ChatFileError(
+            "signed urls are disabled, file.url_secret is not set".to_string(),
+        ));
+    };
+
+    let mut files = Vec::with_capacity(input.urls.len());
+    for url in input.urls {
+        let chat_file: ChatFile = url.parse()?;
+        let url = chat_file.url();
+        // same answer for missing and unreadable files, like `file_handler`
+        if chat_file.ws_id != user.ws_id || !state.can_access_file(&url, user.id).await? {
+            return Err(AppError::FileNotFound(url));
+        }
+        files.push(SignedFile {
+            signed_url: chat_file.signed_url(signer, &user),
+            url,
+        });
+    }
+    Ok(Json(files))
 }
 
 #[cfg(test)]
@@ -99,7 +275,7 @@ mod test {
 
     use anyhow::Result;
     use axum::{
-        extract::{Path, State},
+        extract::{Path, Query, State},
         middleware::from_fn_with_state,
         response::IntoResponse,
         routing::post,
@@ -110,13 +286,21 @@ mod test {
         TestServer,
     };
     use http_body_util::BodyExt;
-    use hyper::StatusCode;
+    use hyper::{
+        header::{CONTENT_DISPOSITION, X_CONTENT_TYPE_OPTIONS},
+        StatusCode,
+    };
     use serde_json::json;
 
     use crate::{
+        config::UploadLimits,
         handlers::{
             auth::{signin_handler, AuthOutput},
-            message::{file_handler, upload_handler},
+            message::{
+                file_handler, sign_files_handler, upload_handler, FileQuery, SignFiles, SignedFile,
+                UploadOutput,
+            },
+            ValidatedJson,
         },
         middlewares::verify_token,
         services::User,
@@ -127,6 +311,73 @@ mod test {
     async fn should_work() -> Result<()> {
         upload_handler_should_work().await?;
         file_handler_should_work().await?;
+        file_handler_thumbnail_should_work().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upload_handler_should_reject_denied_type() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let user = User::new(1, "Tyr Chen", "tchen@acme.org");
+        let token = state.ek.load().sign(user)?;
+
+        let shared_app_state = Arc::new(state);
+        let app = Router::new()
+            .route("/upload", post(upload_handler))
+            .layer(from_fn_with_state(shared_app_state.clone(), verify_token))
+            .with_state(shared_app_state);
+        let server = TestServer::new(app)?;
+
+        // html disguised as an image
+        let form = MultipartForm::new().add_part(
+            "file",
+            Part::bytes(b"<html><script>alert(1)</script></html>".as_slice())
+                .file_name("cat.png")
+                .mime_type("image/png"),
+        );
+        let response = server
+            .post("/upload")
+            .multipart(form)
+            .authorization_bearer(token)
+            .await;
+        assert_eq!(response.status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upload_handler_should_reject_over_quota() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let mut limits = UploadLimits::from(&state.file);
+        limits.quota = state.workspace_storage(1).await? as u64 + 10;
+        state.upload_limits.store(Arc::new(limits));
+        let user = User {
+            id: 1,
+            ws_id: 1,
+            ..Default::default()
+        };
+        let token = state.ek.load().sign(user)?;
+
+        let shared_app_state = Arc::new(state);
+        let app = Router::new()
+            .route("/upload", post(upload_handler))
+            .layer(from_fn_with_state(shared_app_state.clone(), verify_token))
+            .with_state(shared_app_state);
+        let server = TestServer::new(app)?;
+
+        // unique content so the file is never on disk already
+        let content = format!("quota test {}", uuid::Uuid::now_v7());
+        let form = MultipartForm::new().add_part(
+            "file",
+            Part::bytes(content.into_bytes())
+                .file_name("quota.txt")
+                .mime_type("text/plain"),
+        );
+        let response = server
+            .post("/upload")
+            .multipart(form)
+            .authorization_bearer(token)
+            .await;
+        assert_eq!(response.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
         Ok(())
     }
 
@@ -171,15 +422,18 @@ mod test {
 
         assert_eq!(response.status_code(), StatusCode::OK);
         let body = response.as_bytes();
-        let files: Vec<&str> = serde_json::from_slice(body)?;
+        let files: Vec<UploadOutput> = serde_json::from_slice(body)?;
         assert_eq!(files.len(), 2);
         assert_eq!(
-            files,
+            files.iter().map(|f| f.url.as_str()).collect::<Vec<_>>(),
             [
                 "/files/1/8ab/d00/a3253d525b37958381ba1cb044d1cad887.jpg",
                 "/files/1/0a0/a9f/2a6772942557ab5355d76af442f8f65e01.txt"
             ]
         );
+        assert!(files[0].width.is_some() && files[0].height.is_some());
+        assert!(files[0].signed_url.is_some());
+        assert!(files[1].width.is_none() && files[1].height.is_none());
 
         Ok(())
     }
@@ -187,19 +441,173 @@ mod test {
     async fn file_handler_should_work() -> Result<()> {
         let (_tdb, state) = AppState::try_new_test().await?;
         let user = User {
+            id: 1,
             ws_id: 1,
             ..Default::default()
         };
 
+        let state = Arc::new(state);
         let ws_id = 1;
         let path = "0a0/a9f/2a6772942557ab5355d76af442f8f65e01.txt".to_string();
-        let ret = file_handler(Extension(user), State(Arc::new(state)), Path((ws_id, path)))
-            .await?
-            .into_response();
+        let ret = file_handler(
+            Extension(user),
+            State(state.clone()),
+            Path((ws_id, path.clone())),
+            Query(FileQuery::default()),
+        )
+        .await?
+        .into_response();
 
         assert_eq!(ret.status(), StatusCode::OK);
+        assert_eq!(ret.headers()[X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert!(ret.headers().get(CONTENT_DISPOSITION).is_none());
         let body = ret.into_body().collect().await?.to_bytes().to_vec();
         assert_eq!(body, b"Hello, World!");
+
+        // user 4 is not a member of the chat the file is attached to
+        let user = User {
+            id: 4,
+            ws_id: 1,
+            ..Default::default()
+        };
+        let ret = file_handler(
+            Extension(user),
+            State(state),
+            Path((ws_id, path)),
+            Query(FileQuery::default()),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn file_handler_should_reject_traversal() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let state = Arc::new(state);
+        let user = User {
+            id: 1,
+            ws_id: 1,
+            ..Default::default()
+        };
+
+        let paths = [
+            "../../../etc/passwd",
+            "/etc/passwd",
+            "0a0/a9f/../../2/0a0/a9f/2a6772942557ab5355d76af442f8f65e01.txt",
+            "0a0/a9f/2a6772942557ab5355d76af442f8f65e01.txt/..",
+            "0a0/a9f/2a6772942557ab5355d76af442f8f65e01",
+        ];
+        for path in paths {
+            let ret = file_handler(
+                Extension(user.clone()),
+                State(state.clone()),
+                Path((1, path.to_string())),
+                Query(FileQuery::default()),
+            )
+            .await
+            .into_response();
+            assert_eq!(ret.status(), StatusCode::BAD_REQUEST, "{path}");
+        }
+
+        // valid but unknown file
+        let path = "000/000/0000000000000000000000000000000000.txt".to_string();
+        let ret = file_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            Path((1, path.clone())),
+            Query(FileQuery::default()),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::NOT_FOUND);
+
+        // other workspace
+        let ret = file_handler(
+            Extension(user),
+            State(state),
+            Path((2, path)),
+            Query(FileQuery::default()),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sign_files_handler_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let state = Arc::new(state);
+        let user = User {
+            id: 1,
+            ws_id: 1,
+            ..Default::default()
+        };
+        let url = "/files/1/0a0/a9f/2a6772942557ab5355d76af442f8f65e01.txt";
+        let input = || SignFiles {
+            urls: vec![url.to_string()],
+        };
+
+        let ret = sign_files_handler(
+            Extension(user),
+            State(state.clone()),
+            ValidatedJson(input()),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let files: Vec<SignedFile> = serde_json::from_slice(&body)?;
+        assert_eq!(files[0].url, url);
+        assert!(files[0].signed_url.starts_with(&format!("{url}?expires=")));
+
+        // user 4 can't read the file, so it can't get a signed url either
+        let user = User {
+            id: 4,
+            ws_id: 1,
+            ..Default::default()
+        };
+        let ret = sign_files_handler(Extension(user), State(state), ValidatedJson(input()))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    async fn file_handler_thumbnail_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let state = Arc::new(state);
+        let user = User {
+            id: 1,
+            ws_id: 1,
+            ..Default::default()
+        };
+
+        let path = "8ab/d00/a3253d525b37958381ba1cb044d1cad887.jpg".to_string();
+        let query = FileQuery {
+            size: Some("thumb".to_string()),
+        };
+        let ret = file_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            Path((1, path.clone())),
+            Query(query),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let img = image::load_from_memory(&body)?;
+        assert!(img.width() <= 128 && img.height() <= 128);
+
+        // unknown size
+        let query = FileQuery {
+            size: Some("huge".to_string()),
+        };
+        let ret = file_handler(Extension(user), State(state), Path((1, path)), Query(query)).await;
+        assert!(ret.is_err());
         Ok(())
     }
 }
@@ -0,0 +1,48 @@
+use std::sync::Arc;
+
+use axum::{extract::State, response::IntoResponse};
+use chat_core::render_metrics;
+use metrics::gauge;
+
+use crate::AppState;
+
+/// prometheus metrics, the pool gauges are sampled on scrape
+pub(crate) async fn metrics_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let size = state.pool.size() as f64;
+    let idle = state.pool.num_idle() as f64;
+    gauge!("db_pool_connections", "state" => "idle").set(idle);
+    gauge!("db_pool_connections", "state" => "active").set(size - idle);
+    gauge!("db_pool_max_connections").set(state.pool.options().get_max_connections() as f64);
+    render_metrics()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_router;
+    use anyhow::Result;
+    use axum::{body::Body, http::Request};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn metrics_should_expose_requests_and_pool() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let app = get_router(Arc::new(state));
+
+        let req = Request::get("/healthz").body(Body::empty())?;
+        app.clone().oneshot(req).await?;
+        let req = Request::get("/api/1/messages").body(Body::empty())?;
+        app.clone().oneshot(req).await?;
+        let req = Request::get("/metrics").body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        let body = res.into_body().collect().await?.to_bytes();
+        let body = String::from_utf8(body.to_vec())?;
+
+        assert!(body.contains(r#"http_requests_total{method="GET",route="/healthz",status="200"}"#));
+        assert!(body.contains(r#"route="/api/:id/messages""#));
+        assert!(body.contains("http_request_duration_seconds_bucket"));
+        assert!(body.contains(r#"db_pool_connections{state="idle"}"#));
+        Ok(())
+    }
+}
@@ -1,44 +1,88 @@
 mod auth;
 mod chat;
+mod health;
 mod message;
+mod metrics;
 mod model;
+mod openapi;
+mod user;
 
 use std::{sync::Arc, time::Duration};
 
 use axum::{
+    handler::Handler,
     middleware::{from_fn, from_fn_with_state},
     routing::{get, post},
     Router,
 };
+pub(crate) use model::{ChatFile, ValidatedJson, ValidatedQuery};
+
 use chat::{create_chat_handler, list_chats_handler};
-use message::{file_handler, list_message_handler, upload_handler};
+use chat_core::{make_request_span, metrics_handle, track_metrics};
+use message::{file_handler, list_message_handler, sign_files_handler, upload_handler};
+use openapi::ApiDoc;
 use tower::ServiceBuilder;
 
 use tower_http::{
     classify::{ServerErrorsAsFailures, SharedClassifier},
-    trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer},
+    trace::{DefaultOnRequest, DefaultOnResponse, TraceLayer},
     LatencyUnit,
 };
 use tower_http::{compression::CompressionLayer, timeout::TimeoutLayer};
-use tracing::Level;
+use tracing::{Level, Span};
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
 
 use crate::{
-    middlewares::{set_request_id, verify_chat, verify_token, ServerTimeLayer},
+    middlewares::{
+        limit_auth, set_request_id, verify_chat, verify_signed_url, verify_token, RateLimitLayer,
+        ServerTimeLayer,
+    },
     AppState,
 };
 
-pub fn get_router(state: AppState) -> Router {
-    let shared_app_state = Arc::new(state);
+pub fn get_router(shared_app_state: Arc<AppState>) -> Router {
+    // install the recorder before any metric is recorded
+    metrics_handle();
+    // per user limits of `rate_limit.<route>` in config
+    let limit = |route: &str| RateLimitLayer::new(shared_app_state.route_limiters[route].clone());
 
     let api = Router::new()
-        .route("/:id/messages", get(list_message_handler))
+        .route(
+            "/:id/messages",
+            get(list_message_handler.layer(limit("list_messages"))),
+        )
         .layer(from_fn_with_state(shared_app_state.clone(), verify_chat))
-        .route("/chats", get(list_chats_handler).post(create_chat_handler))
-        .route("/upload", post(upload_handler))
-        .route("/files/:ws_id/*path", get(file_handler))
+        .route(
+            "/chats",
+            get(list_chats_handler.layer(limit("list_chats")))
+                .post(create_chat_handler.layer(limit("create_chat"))),
+        )
+        .route("/upload", post(upload_handler.layer(limit("upload"))))
+        .route("/files/sign", post(sign_files_handler))
+        .route(
+            "/me",
+            get(user::get_me_handler).patch(user::update_me_handler),
+        )
+        .route("/me/password", post(user::change_password_handler))
         .layer(from_fn_with_state(shared_app_state.clone(), verify_token))
-        .route("/signup", post(auth::signup_handler))
-        .route("/signin", post(auth::signin_handler))
+        // files accept either a bearer token or a signed url
+        .route(
+            "/files/:ws_id/*path",
+            get(file_handler.layer(limit("files"))).layer(from_fn_with_state(
+                shared_app_state.clone(),
+                verify_signed_url,
+            )),
+        )
+        .merge(
+            Router::new()
+                .route("/signup", post(auth::signup_handler))
+                .route("/signin", post(auth::signin_handler))
+                .route("/email/verify", post(auth::verify_email_handler))
+                .route("/password/forgot", post(auth::forgot_password_handler))
+                .route("/password/reset", post(auth::reset_password_handler))
+                .layer(from_fn_with_state(shared_app_state.clone(), limit_auth)),
+        )
         .layer(
             ServiceBuilder::new()
                 .layer(build_trace_layer())
@@ -50,7 +94,12 @@ pub fn get_router(state: AppState) -> Router {
 
     Router::new()
         .route("/online", get(|| async { "chat sever online" }))
+        .route("/healthz", get(health::healthz_handler))
+        .route("/readyz", get(health::readyz_handler))
+        .route("/metrics", get(metrics::metrics_handler))
         .nest("/api", api)
+        .merge(SwaggerUi::new("/swagger-ui").url("/api/openapi.json", ApiDoc::openapi()))
+        .layer(from_fn(track_metrics))
         .with_state(shared_app_state)
 }
 
@@ -62,9 +111,10 @@ fn build_compression_layer() -> CompressionLayer {
         .zstd(true)
 }
 
-fn build_trace_layer() -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>> {
+fn build_trace_layer(
+) -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>, fn(&axum::extract::Request) -> Span> {
     TraceLayer::new_for_http()
-        .make_span_with(DefaultMakeSpan::new().include_headers(true))
+        .make_span_with(make_request_span as _)
         .on_request(DefaultOnRequest::new().level(Level::INFO))
         .on_response(
             DefaultOnResponse::new()
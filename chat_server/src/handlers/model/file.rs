@@ -3,14 +3,15 @@ use std::{
     str::FromStr,
 };
 
-use crate::AppError;
+use crate::{services::User, signer::UrlSigner, AppError};
+use mime_guess::Mime;
 use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatFile {
     pub ws_id: i64,
-    pub ext: String, // extract ext from filename or mime type
+    pub ext: String, // detect ext from magic bytes, fallback to filename
     pub hash: String,
 }
 
@@ -19,19 +20,34 @@ impl ChatFile {
         let hash = Sha1::digest(data);
         Self {
             ws_id,
-            ext: filename.split('.').last().unwrap_or("txt").to_string(),
+            ext: detect_ext(filename, data),
             hash: hex::encode(hash),
         }
     }
 
+    pub fn mime(&self) -> Mime {
+        mime_guess::from_ext(&self.ext).first_or_octet_stream()
+    }
+
     pub fn url(&self) -> String {
         format!("/files/{}", self.hash_to_path())
     }
 
+    pub fn signed_url(&self, signer: &UrlSigner, user: &User) -> String {
+        signer.signed_url(&self.url(), user.id, user.token_version)
+    }
+
     pub fn path(&self, base_dir: &Path) -> PathBuf {
         base_dir.join(self.hash_to_path())
     }
 
+    // thumbnails live next to the file, e.g. 1/339/807/e635afbeab088ce33206fdf4223a6bb156_thumb.png
+    pub fn thumbnail_path(&self, base_dir: &Path, size: &str) -> PathBuf {
+        let (_, part3) = self.hash.split_at(6);
+        self.path(base_dir)
+            .with_file_name(format!("{}_{}.{}", part3, size, self.ext))
+    }
+
     // split hash into 3 parts, first 2 with 3 chars
     fn hash_to_path(&self) -> String {
         let (part1, part2) = self.hash.split_at(3);
@@ -40,6 +56,31 @@ impl ChatFile {
     }
 }
 
+// trust the content over the filename, so html named .png is still html
+fn detect_ext(filename: &str, data: &[u8]) -> String {
+    if let Some(kind) = infer::get(data) {
+        return kind.extension().to_string();
+    }
+
+    match filename.rsplit_once('.') {
+        Some((_, ext)) if !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric()) => {
+            ext.to_ascii_lowercase()
+        }
+        _ => "bin".to_string(),
+    }
+}
+
+/// whether the browser may render the file inline, others are forced to download
+pub fn is_inline_mime(mime: &Mime) -> bool {
+    match mime.type_().as_str() {
+        "image" => mime.subtype() != "svg",
+        "audio" | "video" => true,
+        "text" => mime.subtype() == "plain",
+        "application" => mime.subtype() == "pdf",
+        _ => false,
+    }
+}
+
 impl FromStr for ChatFile {
     type Err = AppError;
 
@@ -63,7 +104,7 @@ impl FromStr for ChatFile {
         let Ok(ws_id) = parts[0].parse::<i64>() else {
             return Err(AppError::ChatFileError(format!(
                 "Invalid workspace id: {}",
-                parts[1]
+                parts[0]
             )));
         };
 
@@ -74,6 +115,18 @@ impl FromStr for ChatFile {
             )));
         };
 
+        // sha1 hex split as 3/3/34, this also rejects `..` and empty segments
+        if !is_hex(parts[1], 3) || !is_hex(parts[2], 3) || !is_hex(part3, 34) {
+            return Err(AppError::ChatFileError(format!("Invalid file hash: {}", s)));
+        }
+
+        if ext.is_empty() || ext.len() > 16 || !ext.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(AppError::ChatFileError(format!(
+                "Invalid file extension: {}",
+                ext
+            )));
+        }
+
         let hash = format!("{}{}{}", parts[1], parts[2], part3);
         Ok(Self {
             ws_id,
@@ -83,6 +136,10 @@ impl FromStr for ChatFile {
     }
 }
 
+fn is_hex(s: &str, len: usize) -> bool {
+    s.len() == len && s.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,4 +151,66 @@ mod tests {
         assert_eq!(file.ext, "txt");
         assert_eq!(file.hash, "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed");
     }
+
+    #[test]
+    fn chat_file_new_should_detect_ext() {
+        let file = ChatFile::new(1, "cat.png", b"<html><script>alert(1)</script></html>");
+        assert_eq!(file.ext, "html");
+        assert!(!is_inline_mime(&file.mime()));
+
+        let file = ChatFile::new(1, "demo.png", include_bytes!("../../../assets/demo.jpg"));
+        assert_eq!(file.ext, "jpg");
+        assert!(is_inline_mime(&file.mime()));
+
+        let file = ChatFile::new(1, "README", b"hello world");
+        assert_eq!(file.ext, "bin");
+        assert!(!is_inline_mime(&file.mime()));
+    }
+
+    #[test]
+    fn chat_file_from_str_should_work() -> Result<(), AppError> {
+        let file: ChatFile = "/files/1/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed.txt".parse()?;
+        assert_eq!(file.ws_id, 1);
+        assert_eq!(file.ext, "txt");
+        assert_eq!(file.hash, "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed");
+        assert_eq!(
+            file.url(),
+            "/files/1/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed.txt"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn chat_file_from_str_should_reject_invalid_path() {
+        let paths = [
+            "/files/1/../../etc/passwd",
+            "/files/1/2aa/../35c94fcfb415dbe95f408b9ce91ee846ed.txt",
+            "/files/1/2aa/e6c//35c94fcfb415dbe95f408b9ce91ee846ed.txt",
+            "/files/1/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed",
+            "/files/1/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed_thumb.txt",
+            "/files/1/2AA/e6c/35c94fcfb415dbe95f408b9ce91ee846ed.txt",
+            "/files/1/2ag/e6c/35c94fcfb415dbe95f408b9ce91ee846ed.txt",
+            "/files/1/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed.t/xt",
+            "/files/1/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed.tar.gz",
+            "/files//etc/e6c/35c94fcfb415dbe95f408b9ce91ee846ed.txt",
+            "/files/x/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed.txt",
+        ];
+        for path in paths {
+            let ret = path.parse::<ChatFile>();
+            assert!(
+                matches!(ret, Err(AppError::ChatFileError(_))),
+                "{path} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn chat_file_thumbnail_path_should_work() {
+        let file = ChatFile::new(1, "test.png", b"hello world");
+        let path = file.thumbnail_path(Path::new("/tmp"), "thumb");
+        assert_eq!(
+            path,
+            Path::new("/tmp/1/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed_thumb.png")
+        );
+    }
 }
@@ -1,3 +1,7 @@
 mod file;
+mod thumbnail;
+mod validated;
 
 pub use file::*;
+pub use thumbnail::*;
+pub use validated::*;
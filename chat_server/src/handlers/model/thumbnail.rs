@@ -0,0 +1,48 @@
+use std::path::Path;
+
+use image::GenericImageView;
+use tracing::warn;
+
+use crate::config::ThumbnailConfig;
+
+use super::ChatFile;
+
+/// Decode the uploaded data and write the configured thumbnails next to the chat file.
+/// Return the width and height of the image, or None if the data is not an image.
+pub fn generate_thumbnails(
+    chat_file: &ChatFile,
+    data: &[u8],
+    base_dir: &Path,
+    thumbnails: &[ThumbnailConfig],
+) -> Option<(u32, u32)> {
+    let format = image::guess_format(data).ok()?;
+    let img = match image::load_from_memory_with_format(data, format) {
+        Ok(img) => img,
+        Err(e) => {
+            warn!(hash = chat_file.hash, "failed to decode image: {e}");
+            return None;
+        }
+    };
+
+    let (width, height) = img.dimensions();
+    for thumbnail in thumbnails {
+        // never upscale, a small image is served as is
+        if width <= thumbnail.width && height <= thumbnail.height {
+            continue;
+        }
+
+        let path = chat_file.thumbnail_path(base_dir, &thumbnail.name);
+        if path.exists() {
+            continue;
+        }
+
+        if let Err(e) = img
+            .thumbnail(thumbnail.width, thumbnail.height)
+            .save_with_format(&path, format)
+        {
+            warn!(?path, "failed to save thumbnail: {e}");
+        }
+    }
+
+    Some((width, height))
+}
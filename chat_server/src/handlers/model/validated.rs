@@ -0,0 +1,169 @@
+use axum::{
+    async_trait,
+    extract::{FromRequest, FromRequestParts, Query, Request},
+    http::request::Parts,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::de::DeserializeOwned;
+use validator::Validate;
+
+use crate::AppError;
+
+/// `Json` that also runs the `Validate` rules of `T`, invalid fields are rejected with 422.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedJson<T>(pub T);
+
+/// `Query` that also runs the `Validate` rules of `T`, invalid fields are rejected with 422.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(|e| AppError::from(e).into_response())?;
+        value
+            .validate()
+            .map_err(|e| AppError::from(e).into_response())?;
+        Ok(Self(value))
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| AppError::from(e).into_response())?;
+        value
+            .validate()
+            .map_err(|e| AppError::from(e).into_response())?;
+        Ok(Self(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use axum::{
+        body::Body,
+        routing::{get, post},
+        Router,
+    };
+    use http_body_util::BodyExt;
+    use hyper::StatusCode;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        services::{CreateUser, ListMessages},
+        ErrorOutput,
+    };
+
+    async fn handler(ValidatedJson(input): ValidatedJson<CreateUser>) -> String {
+        input.email
+    }
+
+    async fn query_handler(ValidatedQuery(input): ValidatedQuery<ListMessages>) -> String {
+        input.limit.to_string()
+    }
+
+    async fn error_output(res: Response) -> Result<ErrorOutput> {
+        let body = res.into_body().collect().await?.to_bytes();
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    fn signup(body: serde_json::Value) -> Result<Request> {
+        Ok(Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))?)
+    }
+
+    #[tokio::test]
+    async fn validated_json_should_work() -> Result<()> {
+        let app = Router::new().route("/", post(handler));
+
+        let body = serde_json::json!({
+            "workspace": "acme",
+            "fullname": "Tyr Chen",
+            "email": "tyr@acme.org",
+            "password": "123456",
+        });
+        let res = app.clone().oneshot(signup(body)?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body = serde_json::json!({
+            "workspace": "acme",
+            "fullname": "x".repeat(200),
+            "email": "not an email",
+            "password": "",
+        });
+        let res = app.clone().oneshot(signup(body)?).await?;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = res.into_body().collect().await?.to_bytes();
+        let ret: ErrorOutput = serde_json::from_slice(&body)?;
+        assert_eq!(ret.code, "validation_failed");
+        let details = ret.details.unwrap();
+        let fields = details["fields"].as_object().unwrap();
+        let mut names: Vec<_> = fields.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, ["email", "fullname", "password"]);
+
+        // malformed json keeps the status of `Json`
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("Content-Type", "application/json")
+            .body(Body::from("{"))?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error_output(res).await?.code, "invalid_body");
+
+        // wrong content type
+        let req = Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("Content-Type", "text/plain")
+            .body(Body::from("{}"))?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(error_output(res).await?.code, "invalid_body");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn validated_query_should_work() -> Result<()> {
+        let app = Router::new().route("/", get(query_handler));
+
+        let req = Request::builder().uri("/?limit=10").body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = Request::builder().uri("/?limit=0").body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error_output(res).await?.code, "validation_failed");
+
+        let req = Request::builder().uri("/?limit=ten").body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error_output(res).await?.code, "invalid_query");
+        Ok(())
+    }
+}
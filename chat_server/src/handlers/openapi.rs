@@ -0,0 +1,165 @@
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use super::{auth, chat, message, user};
+use crate::{
+    services::{
+        ChangePassword, Chat, ChatType, CreateChat, CreateUser, ForgotPassword, Message,
+        ResetPassword, SigninUser, UpdateUser, User, VerifyEmail,
+    },
+    ErrorOutput,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "chat_server", description = "Chat server api"),
+    paths(
+        auth::signup_handler,
+        auth::signin_handler,
+        auth::verify_email_handler,
+        auth::forgot_password_handler,
+        auth::reset_password_handler,
+        user::get_me_handler,
+        user::update_me_handler,
+        user::change_password_handler,
+        chat::list_chats_handler,
+        chat::create_chat_handler,
+        message::list_message_handler,
+        message::upload_handler,
+        message::file_handler,
+        message::sign_files_handler,
+    ),
+    components(schemas(
+        CreateUser,
+        SigninUser,
+        VerifyEmail,
+        ForgotPassword,
+        ResetPassword,
+        UpdateUser,
+        ChangePassword,
+        auth::AuthOutput,
+        User,
+        Chat,
+        ChatType,
+        CreateChat,
+        Message,
+        message::UploadForm,
+        message::UploadOutput,
+        message::SignFiles,
+        message::SignedFile,
+        ErrorOutput,
+    )),
+    modifiers(&SecurityAddon),
+    tags(
+        (name = "auth", description = "Signup, signin and account recovery"),
+        (name = "user", description = "Profile of the current user"),
+        (name = "chat", description = "Chats of the workspace"),
+        (name = "message", description = "Messages of a chat"),
+        (name = "file", description = "Uploaded files"),
+    )
+)]
+pub(crate) struct ApiDoc;
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "token",
+                SecurityScheme::Http(
+                    HttpBuilder::new()
+                        .scheme(HttpAuthScheme::Bearer)
+                        .bearer_format("JWT")
+                        .build(),
+                ),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{get_router, AppState};
+
+    /// every `/api` route of `get_router`, a route added there must be added here and documented
+    const API_ROUTES: &[(&str, &str)] = &[
+        ("post", "/api/signup"),
+        ("post", "/api/signin"),
+        ("post", "/api/email/verify"),
+        ("post", "/api/password/forgot"),
+        ("post", "/api/password/reset"),
+        ("get", "/api/me"),
+        ("patch", "/api/me"),
+        ("post", "/api/me/password"),
+        ("get", "/api/chats"),
+        ("post", "/api/chats"),
+        ("get", "/api/{id}/messages"),
+        ("post", "/api/upload"),
+        ("post", "/api/files/sign"),
+        ("get", "/api/files/{ws_id}/{path}"),
+    ];
+
+    /// send the request, only an unrouted path or method is 404 or 405
+    async fn is_routed(app: &axum::Router, method: &str, path: &str) -> Result<bool> {
+        // any value reaches the handler or its middlewares
+        let uri = path
+            .replace("{id}", "1")
+            .replace("{ws_id}", "1")
+            .replace("{path}", "0a0/a9f/2a6772942557ab5355d76af442f8f65e01.txt");
+        let req = Request::builder()
+            .method(method.to_uppercase().as_str())
+            .uri(&uri)
+            .body(Body::empty())?;
+        let status = app.clone().oneshot(req).await?.status();
+        Ok(!matches!(
+            status,
+            StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED
+        ))
+    }
+
+    #[tokio::test]
+    async fn openapi_paths_should_be_routed() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let app = get_router(Arc::new(state));
+
+        let spec = serde_json::to_value(ApiDoc::openapi())?;
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            for method in item.as_object().unwrap().keys() {
+                assert!(
+                    is_routed(&app, method, path).await?,
+                    "{method} {path} is documented but not routed"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn api_routes_should_be_documented() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let app = get_router(Arc::new(state));
+
+        let spec = serde_json::to_value(ApiDoc::openapi())?;
+        for (method, path) in API_ROUTES {
+            assert!(
+                is_routed(&app, method, path).await?,
+                "{method} {path} is listed but not routed"
+            );
+            assert!(
+                spec["paths"][path][method].is_object(),
+                "{method} {path} is routed but not documented"
+            );
+        }
+        Ok(())
+    }
+}
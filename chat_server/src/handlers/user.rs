@@ -0,0 +1,249 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+
+use super::ValidatedJson;
+use crate::{
+    handlers::auth::AuthOutput,
+    services::{ChangePassword, UpdateUser, User},
+    AppError, AppState, ErrorOutput,
+};
+
+#[utoipa::path(
+    get,
+    path = "/api/me",
+    tag = "user",
+    security(("token" = [])),
+    responses(
+        (status = 200, description = "Profile of the current user", body = User),
+    )
+)]
+pub(crate) async fn get_me_handler(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state
+        .find_user_by_id(user.id)
+        .await?
+        .ok_or(AppError::UserNotFound(user.id))?;
+    Ok((StatusCode::OK, Json(user)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/me",
+    tag = "user",
+    security(("token" = [])),
+    request_body = UpdateUser,
+    responses(
+        (status = 200, description = "Updated profile", body = User),
+        (status = 400, description = "Avatar is not an image of the workspace", body = ErrorOutput),
+        (status = 404, description = "Avatar not found or not uploaded by the user", body = ErrorOutput),
+        (status = 422, description = "Invalid fields", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn update_me_handler(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(input): ValidatedJson<UpdateUser>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = state.update_user(&user, &input).await?;
+    Ok((StatusCode::OK, Json(user)))
+}
+
+/// other sessions are revoked, the caller gets a fresh token
+#[utoipa::path(
+    post,
+    path = "/api/me/password",
+    tag = "user",
+    security(("token" = [])),
+    request_body = ChangePassword,
+    responses(
+        (status = 200, description = "Password changed", body = AuthOutput),
+        (status = 403, description = "Invalid old password", body = ErrorOutput),
+        (status = 422, description = "Invalid fields", body = ErrorOutput),
+    )
+)]
+pub(crate) async fn change_password_handler(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+    ValidatedJson(input): ValidatedJson<ChangePassword>,
+) -> Result<impl IntoResponse, AppError> {
+    match state.change_password(user.id, &input).await? {
+        Some(user) => {
+            let token = state.ek.load().sign(user)?;
+            Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
+        }
+        None => {
+            let body = Json(ErrorOutput::new("invalid_credentials", "Invalid password"));
+            Ok((StatusCode::FORBIDDEN, body).into_response())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use axum::{
+        body::Body, extract::Request, middleware::from_fn_with_state, routing::post, Router,
+    };
+    use http_body_util::BodyExt;
+    use hyper::StatusCode;
+    use tower::ServiceExt;
+
+    use crate::{
+        handlers::auth::AuthOutput,
+        middlewares::verify_token,
+        services::{SigninUser, UpdateUser},
+        AppState,
+    };
+
+    use super::*;
+
+    fn test_user() -> User {
+        User {
+            id: 1,
+            ws_id: 1,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn update_me_handler_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let state = Arc::new(state);
+        let input = UpdateUser {
+            fullname: Some("Tyr".to_string()),
+            ..Default::default()
+        };
+        let ret = update_me_handler(
+            Extension(test_user()),
+            State(state.clone()),
+            ValidatedJson(input),
+        )
+        .await?
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let user: User = serde_json::from_slice(&body)?;
+        assert_eq!(user.fullname, "Tyr");
+        assert_eq!(user.email, "tchen@acme.org");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_me_handler_should_reject_bad_avatar() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let state = Arc::new(state);
+
+        // not an image
+        let input = UpdateUser {
+            avatar_url: Some("/files/1/0a0/a9f/2a6772942557ab5355d76af442f8f65e01.txt".to_string()),
+            ..Default::default()
+        };
+        let ret = update_me_handler(
+            Extension(test_user()),
+            State(state.clone()),
+            ValidatedJson(input),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::BAD_REQUEST);
+
+        // an image the user never uploaded
+        let input = UpdateUser {
+            avatar_url: Some("/files/1/0a0/a9f/2a6772942557ab5355d76af442f8f65e01.png".to_string()),
+            ..Default::default()
+        };
+        let ret = update_me_handler(
+            Extension(test_user()),
+            State(state.clone()),
+            ValidatedJson(input),
+        )
+        .await
+        .into_response();
+        assert_eq!(ret.status(), StatusCode::NOT_FOUND);
+
+        // user 2 can read the image of private chat 2 but didn't upload it
+        let user = User {
+            id: 2,
+            ws_id: 1,
+            ..Default::default()
+        };
+        let input = UpdateUser {
+            avatar_url: Some("/files/1/8ab/d00/a3253d525b37958381ba1cb044d1cad887.jpg".to_string()),
+            ..Default::default()
+        };
+        let ret = update_me_handler(Extension(user), State(state), ValidatedJson(input))
+            .await
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn avatar_should_be_visible_in_workspace() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let url = "/files/1/0a0/a9f/2a6772942557ab5355d76af442f8f65e01.png";
+        state.add_file(1, url, 1, 42).await?;
+        assert!(!state.can_access_file(url, 2).await?);
+
+        let input = UpdateUser {
+            avatar_url: Some(url.to_string()),
+            ..Default::default()
+        };
+        let user = state.update_user(&test_user(), &input).await?;
+        assert_eq!(user.avatar_url.as_deref(), Some(url));
+        assert!(state.can_access_file(url, 2).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn change_password_should_revoke_other_sessions() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let user = state
+            .verify_user(&SigninUser::new("tchen@acme.org", "123456"))
+            .await?
+            .unwrap();
+        let old_token = state.ek.load().sign(user)?;
+
+        let state = Arc::new(state);
+        let app = Router::new()
+            .route("/me/password", post(change_password_handler))
+            .layer(from_fn_with_state(state.clone(), verify_token))
+            .with_state(state);
+        let change = |token: &str, old_password: &str| {
+            let body = serde_json::json!({
+                "old_password": old_password,
+                "new_password": "new password",
+            });
+            Request::builder()
+                .method("POST")
+                .uri("/me/password")
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+        };
+
+        // wrong old password
+        let res = app.clone().oneshot(change(&old_token, "bad")?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res = app.clone().oneshot(change(&old_token, "123456")?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await?.to_bytes();
+        let ret: AuthOutput = serde_json::from_slice(&body)?;
+
+        // old token is revoked, the new one works
+        let res = app
+            .clone()
+            .oneshot(change(&old_token, "new password")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app.oneshot(change(&ret.token, "new password")?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(())
+    }
+}
@@ -2,12 +2,17 @@ mod app_state;
 mod config;
 mod error;
 mod handlers;
-mod jwt;
+mod mailer;
 mod middlewares;
+mod migration;
 mod services;
+mod signer;
 
 pub use app_state::AppState;
 pub use config::Config;
 pub use error::*;
 pub use handlers::get_router;
-pub use services::CreateUser;
+pub use migration::{
+    latest_version, migrate_up, migration_status, prepare_schema, MigrateConfig, MigrationStatus,
+};
+pub use services::{CreateUser, Workspace};
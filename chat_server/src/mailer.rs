@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+use tracing::info;
+
+use crate::{config::MailConfig, AppError};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mail {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Outbound mail, implement it to plug in a real mail service.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: Mail) -> Result<(), AppError>;
+}
+
+/// Default mailer, append mails as json lines to the outbox file, or log them if not set.
+/// The body is only logged with `mail.log_body`, it carries tokens.
+pub struct FileMailer {
+    outbox: Option<PathBuf>,
+    log_body: bool,
+}
+
+impl FileMailer {
+    pub fn new(config: &MailConfig) -> Self {
+        Self {
+            outbox: config.outbox.clone(),
+            log_body: config.log_body,
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: Mail) -> Result<(), AppError> {
+        let Some(outbox) = &self.outbox else {
+            if self.log_body {
+                info!(
+                    to = mail.to,
+                    subject = mail.subject,
+                    body = mail.body,
+                    "send mail"
+                );
+            } else {
+                info!(to = mail.to, subject = mail.subject, "send mail");
+            }
+            return Ok(());
+        };
+
+        let mut line = serde_json::to_string(&mail).map_err(std::io::Error::other)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(outbox)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        // tokio writes in the background, make sure the mail is on disk once sent
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn file_mailer_should_work() -> Result<()> {
+        let outbox = std::env::temp_dir().join(format!("outbox-{}.jsonl", uuid::Uuid::now_v7()));
+        let mailer = FileMailer::new(&MailConfig {
+            outbox: Some(outbox.clone()),
+            ..Default::default()
+        });
+        let mail = Mail {
+            from: "noreply@acme.org".to_string(),
+            to: "tchen@acme.org".to_string(),
+            subject: "hello".to_string(),
+            body: "world".to_string(),
+        };
+        mailer.send(mail.clone()).await?;
+        mailer.send(mail).await?;
+
+        let content = tokio::fs::read_to_string(&outbox).await?;
+        let mails: Vec<Mail> = content
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(mails.len(), 2);
+        assert_eq!(mails[1].to, "tchen@acme.org");
+        tokio::fs::remove_file(outbox).await?;
+        Ok(())
+    }
+}
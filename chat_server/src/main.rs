@@ -1,46 +1,118 @@
-use anyhow::{Context, Result};
-use chat_server::{get_router, AppState, Config};
+use anyhow::{bail, Result};
+use chat_core::{init_tracing, spawn_reloader, ConfigErrors, LogFilter, Shutdown};
+use chat_server::{get_router, latest_version, migrate_up, migration_status, AppState, Config};
 use dotenv::dotenv;
-use std::env;
+use std::{env, net::SocketAddr, sync::Arc, time::Duration};
 use tokio::net::TcpListener;
-use tracing::{info, level_filters::LevelFilter};
-use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
+use tracing::info;
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // 读取.env文件
     dotenv().ok();
-    // 注册日志
-    let layer = Layer::new().with_filter(LevelFilter::INFO);
-    tracing_subscriber::registry().with(layer).init();
 
-    // 读取配置文件路径
-    let config_path =
-        env::var("CHAT_CONFIG_PATH").context("can't read the env $CHAT_CONFIG_PATH$")?;
-    // info!("config_path: {}", config_path);
+    // 打印生效的配置, 隐藏密钥
+    if env::args().any(|arg| arg == "--print-config") {
+        print!("{}", Config::load().await?.redacted()?);
+        return Ok(());
+    }
+
+    // 数据库迁移: chat_server migrate up|status
+    let args: Vec<_> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("migrate") {
+        return migrate(args.get(1).map(String::as_str)).await;
+    }
+
+    // 读取配置: 默认值, $CHAT_CONFIG_PATH 的yaml文件, CHAT_* 环境变量
+    let config = Config::try_new().await?;
 
-    // 读取配置
-    let config = Config::try_new(&config_path).await?;
-    // info!("config: {:?}", config);
+    // 注册日志, 配置了otlp_endpoint时导出trace
+    let telemetry = init_tracing("chat_server", &config.log, &config.telemetry)?;
 
     // 构造监听路径
     let addr = format!("{}:{}", config.listen.host, config.listen.port);
+    let drain_timeout = Duration::from_secs(config.listen.drain_timeout);
+    let watched_files = config.watched_files();
     // info!("addr: {:?}", addr);
 
     // 构造应用状态
-    let app_state = AppState::try_new(config).await?;
+    let app_state = Arc::new(AppState::try_new(config).await?);
     // info!("app_state: {:?}", app_state);
 
+    // 清理未引用的文件
+    app_state.clone().spawn_file_gc();
+
+    // 收到SIGHUP或配置文件变化时重新加载日志过滤, 密钥和限制, 无效的配置不生效
+    let state = app_state.clone();
+    let log_filter = telemetry.log_filter();
+    spawn_reloader(watched_files, RELOAD_INTERVAL, move || {
+        let state = state.clone();
+        let log_filter = log_filter.clone();
+        async move {
+            let config = Config::load().await?;
+            config.validate().await?;
+            let filter = LogFilter::parse(&config.log.directives())?;
+            state.reload(&config)?;
+            log_filter.set(filter)
+        }
+    });
+
     // 构造应用路由
-    let app = get_router(app_state);
+    let app = get_router(app_state.clone());
 
     // 监听端口
     let listener = TcpListener::bind(&addr).await?;
     info!("chat server listen on {addr}");
 
-    // 绑定axum处理
-    axum::serve(listener, app).await?;
+    // 绑定axum处理, 收到SIGINT/SIGTERM后等待处理中的请求结束
+    let shutdown = Shutdown::listen();
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.clone().wait());
+    shutdown.drain(server, drain_timeout).await?;
+
+    // 关闭数据库连接池
+    app_state.close().await;
     info!("chat server quit");
 
     Ok(())
 }
+
+/// `migrate up` applies the pending migrations, `migrate status` lists them. Only `db` of the
+/// config is needed
+async fn migrate(command: Option<&str>) -> Result<()> {
+    let config = Config::load().await?;
+    let mut errors = ConfigErrors::default();
+    config.db.validate(&mut errors);
+    errors.into_result()?;
+    let pool = config.db.connect().await?;
+
+    match command {
+        Some("up") => {
+            let applied = migrate_up(&pool).await?;
+            if applied.is_empty() {
+                println!("schema is up to date at version {}", latest_version());
+            }
+            for version in applied {
+                println!("applied {version}");
+            }
+        }
+        Some("status") => {
+            for status in migration_status(&pool).await? {
+                let state = if status.applied { "applied" } else { "pending" };
+                let description = status
+                    .description
+                    .as_deref()
+                    .unwrap_or("unknown to this binary");
+                println!("{} {state:<7} {description}", status.version);
+            }
+        }
+        _ => bail!("usage: chat_server migrate <up|status>"),
+    }
+    pool.close().await;
+    Ok(())
+}
@@ -1,33 +1,106 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::{Request, State},
-    http::StatusCode,
+    extract::{FromRequestParts, Query, Request, State},
+    http::request::Parts,
     middleware::Next,
     response::{IntoResponse, Response},
 };
 use axum_auth::AuthBearer;
+use serde::Deserialize;
 use tracing::warn;
 
-use crate::AppState;
+use crate::{services::User, AppError, AppState};
 
 pub async fn verify_token(
-    AuthBearer(token): AuthBearer,
     State(state): State<Arc<AppState>>,
-    mut request: Request,
+    request: Request,
     next: Next,
 ) -> Response {
-    match state.dk.verify(&token) {
+    let (mut parts, body) = request.into_parts();
+    match bearer_user(&state, &mut parts).await {
         Ok(user) => {
-            request.extensions_mut().insert(user);
-            next.run(request).await
+            parts.extensions.insert(user);
+            next.run(Request::from_parts(parts, body)).await
         }
-        Err(e) => {
-            let msg = format!("verify token failed: {:?}", e);
-            warn!(msg);
-            (StatusCode::FORBIDDEN, msg).into_response()
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn bearer_user(state: &Arc<AppState>, parts: &mut Parts) -> Result<User, AppError> {
+    let AuthBearer(token) = AuthBearer::from_request_parts(parts, state)
+        .await
+        .map_err(|(_, msg)| AppError::InvalidToken(msg.to_string()))?;
+    let user = state.dk.verify(&token).map_err(|e| {
+        warn!("verify token failed: {e}");
+        AppError::Unauthenticated(format!("verify token failed: {e}"))
+    })?;
+    if !state.is_token_current(&user).await? {
+        warn!(user_id = user.id, "token has been revoked");
+        return Err(AppError::TokenRevoked(user.id));
+    }
+    Ok(user)
+}
+
+#[derive(Debug, Deserialize)]
+struct SignedUrlQuery {
+    expires: i64,
+    user: i64,
+    sig: String,
+}
+
+/// accept a signed url produced by `UrlSigner` in place of a bearer token, unless the user has
+/// been disabled or revoked the tokens since it was signed
+pub async fn verify_signed_url(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let user = match Query::<SignedUrlQuery>::from_request_parts(&mut parts, &state).await {
+        Ok(Query(query)) => signed_url_user(&state, &parts, query).await,
+        // no signature, fallback to bearer token
+        Err(_) => bearer_user(&state, &mut parts).await,
+    };
+    match user {
+        Ok(user) => {
+            parts.extensions.insert(user);
+            next.run(Request::from_parts(parts, body)).await
         }
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn signed_url_user(
+    state: &AppState,
+    parts: &Parts,
+    query: SignedUrlQuery,
+) -> Result<User, AppError> {
+    let user = state
+        .find_user_by_id(query.user)
+        .await?
+        .ok_or_else(|| AppError::Unauthenticated("user of signed url not found".to_string()))?;
+    // the signature is bound to the token version, a url signed before the revocation fails
+    let is_valid = state.signer.as_ref().is_some_and(|signer| {
+        signer.verify(
+            parts.uri.path(),
+            user.id,
+            user.token_version,
+            query.expires,
+            &query.sig,
+        )
+    });
+    if !is_valid {
+        warn!(user_id = query.user, "verify signed url failed");
+        return Err(AppError::Unauthenticated(
+            "verify signed url failed".to_string(),
+        ));
+    }
+    if !state.is_token_current(&user).await? {
+        warn!(user_id = user.id, "user of signed url has been disabled");
+        return Err(AppError::TokenRevoked(user.id));
     }
+    Ok(user)
 }
 
 #[cfg(test)]
@@ -37,27 +110,33 @@ mod tests {
     use axum::{
         body::Body, extract::Request, middleware::from_fn_with_state, routing::get, Router,
     };
+    use http_body_util::BodyExt;
     use hyper::StatusCode;
     use tower::ServiceExt;
 
     use crate::{
-        middlewares::{test_handler, verify_token},
+        middlewares::{test_handler, verify_signed_url, verify_token},
         services::User,
-        AppState,
+        AppState, ErrorOutput,
     };
 
+    async fn error_code(res: axum::response::Response) -> anyhow::Result<String> {
+        let body = res.into_body().collect().await?.to_bytes();
+        Ok(serde_json::from_slice::<ErrorOutput>(&body)?.code)
+    }
+
     #[tokio::test]
     async fn verify_token_should_work() -> anyhow::Result<()> {
         let (_tdb, state) = AppState::try_new_test().await?;
         let user = User::new(1, "Tyr Chen", "tchen@acme.org");
-        let token = state.ek.sign(user)?;
+        let token = state.ek.load().sign(user)?;
 
         let state = Arc::new(state);
 
         let app = Router::new()
             .route("/", get(test_handler))
             .layer(from_fn_with_state(state.clone(), verify_token))
-            .with_state(state);
+            .with_state(state.clone());
 
         // good token
         let req = Request::builder()
@@ -71,6 +150,7 @@ mod tests {
         let req = Request::builder().uri("/").body(Body::empty())?;
         let res = app.clone().oneshot(req).await?;
         assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error_code(res).await?, "invalid_token");
 
         // bad token
         let req = Request::builder()
@@ -79,6 +159,98 @@ mod tests {
             .body(Body::empty())?;
         let res = app.clone().oneshot(req).await?;
         assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(error_code(res).await?, "unauthenticated");
+
+        // revoked token
+        sqlx::query("UPDATE users SET token_version = token_version + 1 WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        let req = Request::builder()
+            .uri("/")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(error_code(res).await?, "token_revoked");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verify_signed_url_should_work() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let user = User::new(1, "Tyr Chen", "tchen@acme.org");
+        let token = state.ek.load().sign(user)?;
+        let path = "/files/1/0a0/a9f/2a6772942557ab5355d76af442f8f65e01.txt";
+        let url = state.signer.as_ref().unwrap().signed_url(path, 1, 0);
+
+        let state = Arc::new(state);
+        let app = Router::new()
+            .route(
+                "/files/:ws_id/*path",
+                get(test_handler).layer(from_fn_with_state(state.clone(), verify_signed_url)),
+            )
+            .with_state(state);
+
+        // good signature
+        let req = Request::builder().uri(&url).body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // signature bound to another user
+        let req = Request::builder()
+            .uri(url.replace("user=1", "user=2"))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // bearer token still works
+        let req = Request::builder()
+            .uri(path)
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // neither signature nor token
+        let req = Request::builder().uri(path).body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verify_signed_url_should_reject_revoked_user() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let path = "/files/1/0a0/a9f/2a6772942557ab5355d76af442f8f65e01.txt";
+        let url = state.signer.as_ref().unwrap().signed_url(path, 1, 0);
+
+        let state = Arc::new(state);
+        let app = Router::new()
+            .route(
+                "/files/:ws_id/*path",
+                get(test_handler).layer(from_fn_with_state(state.clone(), verify_signed_url)),
+            )
+            .with_state(state.clone());
+
+        // password reset
+        state.set_password(1, "new password").await?;
+        let req = Request::builder().uri(&url).body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(error_code(res).await?, "unauthenticated");
+
+        // disabled user, even with a signature of the current token version
+        state.set_user_disabled(2, true).await?;
+        let user = state.find_user_by_id(2).await?.unwrap();
+        let url = state
+            .signer
+            .as_ref()
+            .unwrap()
+            .signed_url(path, 2, user.token_version);
+        let req = Request::builder().uri(&url).body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert_eq!(error_code(res).await?, "token_revoked");
         Ok(())
     }
 }
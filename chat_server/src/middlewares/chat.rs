@@ -6,20 +6,26 @@ use axum::{
     middleware::Next,
     response::{IntoResponse, Response},
 };
+use tracing::{debug, warn};
 
 pub async fn verify_chat(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
     let (mut parts, body) = req.into_parts();
-    let Path(chat_id) = Path::<i64>::from_request_parts(&mut parts, &state)
-        .await
-        .unwrap();
+    let chat_id = match Path::<i64>::from_request_parts(&mut parts, &state).await {
+        Ok(Path(chat_id)) => chat_id,
+        Err(e) => return AppError::from(e).into_response(),
+    };
 
     let user = parts.extensions.get::<User>().unwrap();
-    println!("{},{}", chat_id, user.id);
+    debug!(user_id = user.id, chat_id, "verify chat membership");
     if !state
         .is_chat_member(chat_id, user.id)
         .await
         .unwrap_or_default()
     {
+        warn!(
+            user_id = user.id,
+            chat_id, "user is not a member of the chat"
+        );
         let err = AppError::VerifyChat(format!(
             "User {} are not a member of chat {chat_id}",
             user.id
@@ -40,8 +46,11 @@ mod tests {
     use axum::{
         body::Body, http::StatusCode, middleware::from_fn_with_state, routing::get, Router,
     };
+    use http_body_util::BodyExt;
     use tower::ServiceExt;
 
+    use crate::ErrorOutput;
+
     async fn handler(_req: Request) -> impl IntoResponse {
         (StatusCode::OK, "ok")
     }
@@ -52,7 +61,7 @@ mod tests {
         let state = Arc::new(state);
 
         let user = User::new(1, "Tyr Chen", "tchen@acme.org");
-        let token = state.ek.sign(user)?;
+        let token = state.ek.load().sign(user)?;
 
         let app = Router::new()
             .route("/chat/:id/messages", get(handler))
@@ -73,9 +82,20 @@ mod tests {
             .uri("/chat/5/messages")
             .header("Authorization", format!("Bearer {}", token))
             .body(Body::empty())?;
-        let res = app.oneshot(req).await?;
+        let res = app.clone().oneshot(req).await?;
         assert_eq!(res.status(), StatusCode::FORBIDDEN);
 
+        // chat id not a number
+        let req = Request::builder()
+            .uri("/chat/abc/messages")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = res.into_body().collect().await?.to_bytes();
+        let ret: ErrorOutput = serde_json::from_slice(&body)?;
+        assert_eq!(ret.code, "invalid_path");
+
         Ok(())
     }
 }
@@ -1,15 +1,22 @@
 mod auth;
 mod chat;
+mod rate_limit;
 mod request_id;
 mod server_time;
 
-pub use auth::verify_token;
+pub use auth::{verify_signed_url, verify_token};
 pub use chat::verify_chat;
+pub(crate) use rate_limit::too_many_requests;
+pub use rate_limit::{limit_auth, ClientIp, RateLimitLayer, ReloadableLimiter, LIMITED_ROUTES};
 pub use request_id::set_request_id;
 pub use server_time::ServerTimeLayer;
 
 const REQUEST_ID_HEADER: &str = "x-request-id";
 const SERVER_TIME_HEADER: &str = "x-server-time";
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+const RATE_LIMIT_LIMIT_HEADER: &str = "x-ratelimit-limit";
+const RATE_LIMIT_REMAINING_HEADER: &str = "x-ratelimit-remaining";
+const RATE_LIMIT_RESET_HEADER: &str = "x-ratelimit-reset";
 
 #[cfg(test)]
 async fn test_handler(_req: axum::extract::Request) -> impl axum::response::IntoResponse {
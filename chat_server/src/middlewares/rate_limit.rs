@@ -0,0 +1,475 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use arc_swap::ArcSwapOption;
+use axum::{
+    async_trait,
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, FromRequestParts, Request, State},
+    http::{request::Parts, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::future::BoxFuture;
+use hyper::header::RETRY_AFTER;
+use serde::Deserialize;
+use tokio::time::Instant;
+use tower::{Layer, Service};
+use tracing::warn;
+
+use crate::{config::RateLimitConfig, services::User, AppError, AppState};
+
+use super::{
+    FORWARDED_FOR_HEADER, RATE_LIMIT_LIMIT_HEADER, RATE_LIMIT_REMAINING_HEADER,
+    RATE_LIMIT_RESET_HEADER,
+};
+
+// buckets that are full again are dropped once the map grows beyond this
+const MAX_BUCKETS: usize = 10_000;
+// signin/signup bodies are tiny json objects
+const MAX_AUTH_BODY: usize = 64 * 1024;
+
+/// routes limited by `rate_limit.<route>` in config
+pub const LIMITED_ROUTES: [&str; 5] = [
+    "list_messages",
+    "list_chats",
+    "create_chat",
+    "upload",
+    "files",
+];
+
+/// Token bucket rate limiter keyed by an arbitrary string, e.g. ip or email.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    burst: f64,
+    // tokens refilled per second
+    rate: f64,
+    buckets: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            config: config.clone(),
+            burst: config.burst as f64,
+            rate: config.per_minute as f64 / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.burst as u32
+    }
+
+    /// take a token for the key and return the tokens left,
+    /// or return how long to wait for the next one
+    pub fn check(&self, key: &str) -> Result<u32, Duration> {
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+        let now = Instant::now();
+        if buckets.len() > MAX_BUCKETS {
+            let (burst, rate) = (self.burst, self.rate);
+            buckets.retain(|_, (tokens, last)| {
+                *tokens + now.duration_since(*last).as_secs_f64() * rate < burst
+            });
+        }
+
+        let (tokens, last) = buckets.entry(key.to_string()).or_insert((self.burst, now));
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * self.rate).min(self.burst);
+        *last = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(*tokens as u32)
+        } else if self.rate > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - *tokens) / self.rate))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+}
+
+/// A rate limiter replaced when its config changes on reload, none if not configured.
+#[derive(Default)]
+pub struct ReloadableLimiter(ArcSwapOption<RateLimiter>);
+
+impl ReloadableLimiter {
+    pub fn new(config: Option<&RateLimitConfig>) -> Self {
+        Self(ArcSwapOption::from_pointee(config.map(RateLimiter::new)))
+    }
+
+    pub fn load(&self) -> Option<Arc<RateLimiter>> {
+        self.0.load_full()
+    }
+
+    /// swap in a limiter with empty buckets if the config changed
+    pub fn update(&self, config: Option<&RateLimitConfig>) {
+        let current = self.0.load();
+        if current.as_ref().map(|limiter| &limiter.config) != config {
+            self.0
+                .store(config.map(|config| Arc::new(RateLimiter::new(config))));
+        }
+    }
+}
+
+/// Client ip, the peer address of the connection or, behind one of `listen.trusted_proxies`,
+/// the right-most address of `x-forwarded-for` that is not a trusted proxy.
+pub struct ClientIp(pub String);
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for ClientIp {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let ip = peer
+            .map(|peer| client_ip(peer, &parts.headers, &state.listen.trusted_proxies))
+            .map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+        Ok(Self(ip))
+    }
+}
+
+// every proxy appends the address it got the request from, so the entries left of the
+// first untrusted one from the right may be forged by the client
+fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &[IpAddr]) -> IpAddr {
+    if !trusted.contains(&peer) {
+        return peer;
+    }
+    let forwarded = headers
+        .get_all(FORWARDED_FOR_HEADER)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect::<Vec<_>>();
+    for hop in forwarded.into_iter().rev() {
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) if trusted.contains(&ip) => continue,
+            Ok(ip) => return ip,
+            // not written by a trusted proxy
+            Err(_) => break,
+        }
+    }
+    peer
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthBody {
+    email: String,
+}
+
+/// rate limit signin/signup by client ip and by the email in the json body
+pub async fn limit_auth(State(state): State<Arc<AppState>>, req: Request, next: Next) -> Response {
+    let Some(limiter) = state.auth_limiter.load() else {
+        return next.run(req).await;
+    };
+
+    let (mut parts, body) = req.into_parts();
+    let Ok(ClientIp(ip)) = ClientIp::from_request_parts(&mut parts, &state).await;
+    let body = match to_bytes(body, MAX_AUTH_BODY).await {
+        Ok(body) => body,
+        Err(e) => return AppError::PayloadTooLarge(e.to_string()).into_response(),
+    };
+
+    let mut keys = vec![format!("ip:{ip}")];
+    if let Ok(input) = serde_json::from_slice::<AuthBody>(&body) {
+        keys.push(format!("email:{}", input.email.to_lowercase()));
+    }
+    for key in keys {
+        if let Err(retry_after) = limiter.check(&key) {
+            warn!(key, "rate limit exceeded");
+            return too_many_requests(retry_after);
+        }
+    }
+
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+/// `AppError::TooManyRequests`, which sets `Retry-After`
+pub(crate) fn too_many_requests(retry_after: Duration) -> Response {
+    let secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    AppError::TooManyRequests(secs.min(u32::MAX as u64)).into_response()
+}
+
+/// Per user rate limit, keyed by the `User` extension inserted by `verify_token`.
+/// Requests without a user and routes without a configured limit are not limited.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limiter: Arc<ReloadableLimiter>,
+}
+
+impl RateLimitLayer {
+    pub fn new(limiter: Arc<ReloadableLimiter>) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitMiddleware {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitMiddleware<S> {
+    inner: S,
+    limiter: Arc<ReloadableLimiter>,
+}
+
+impl<S> Service<Request> for RateLimitMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let user_id = request.extensions().get::<User>().map(|user| user.id);
+        let (Some(limiter), Some(user_id)) = (self.limiter.load(), user_id) else {
+            return Box::pin(self.inner.call(request));
+        };
+
+        let limit = HeaderValue::from(limiter.limit());
+        match limiter.check(&format!("user:{user_id}")) {
+            Ok(remaining) => {
+                let future = self.inner.call(request);
+                Box::pin(async move {
+                    let mut res = future.await?;
+                    let headers = res.headers_mut();
+                    headers.insert(RATE_LIMIT_LIMIT_HEADER, limit);
+                    headers.insert(RATE_LIMIT_REMAINING_HEADER, HeaderValue::from(remaining));
+                    Ok(res)
+                })
+            }
+            Err(retry_after) => {
+                warn!(user_id, "rate limit exceeded");
+                let mut res = too_many_requests(retry_after);
+                let reset = res.headers()[RETRY_AFTER].clone();
+                let headers = res.headers_mut();
+                headers.insert(RATE_LIMIT_LIMIT_HEADER, limit);
+                headers.insert(RATE_LIMIT_REMAINING_HEADER, HeaderValue::from(0));
+                headers.insert(RATE_LIMIT_RESET_HEADER, reset);
+                Box::pin(async move { Ok(res) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::Body,
+        extract::Request,
+        middleware::{from_fn, from_fn_with_state},
+        routing::{get, post},
+        Router,
+    };
+    use hyper::{header::RETRY_AFTER, StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::middlewares::test_handler;
+
+    #[test]
+    fn rate_limiter_should_work() {
+        let limiter = RateLimiter::new(&RateLimitConfig {
+            burst: 2,
+            per_minute: 60,
+        });
+        assert_eq!(limiter.check("a"), Ok(1));
+        assert_eq!(limiter.check("a"), Ok(0));
+        let retry_after = limiter.check("a").unwrap_err();
+        assert!(retry_after <= Duration::from_secs(1));
+        // keys are independent
+        assert!(limiter.check("b").is_ok());
+    }
+
+    #[test]
+    fn reloadable_limiter_should_keep_buckets_of_unchanged_config() {
+        let config = RateLimitConfig {
+            burst: 1,
+            per_minute: 1,
+        };
+        let limiter = ReloadableLimiter::new(Some(&config));
+        assert!(limiter.load().unwrap().check("a").is_ok());
+
+        limiter.update(Some(&config));
+        assert!(limiter.load().unwrap().check("a").is_err());
+
+        limiter.update(Some(&RateLimitConfig {
+            burst: 2,
+            per_minute: 1,
+        }));
+        let current = limiter.load().unwrap();
+        assert_eq!(current.limit(), 2);
+        assert!(current.check("a").is_ok());
+
+        limiter.update(None);
+        assert!(limiter.load().is_none());
+    }
+
+    #[tokio::test]
+    async fn limit_auth_should_work() -> anyhow::Result<()> {
+        let (_tdb, mut state) = AppState::try_new_test().await?;
+        state.auth_limiter = ReloadableLimiter::new(Some(&RateLimitConfig {
+            burst: 1,
+            per_minute: 1,
+        }));
+        let state = Arc::new(state);
+        let app = Router::new()
+            .route("/signin", post(test_handler))
+            .layer(from_fn_with_state(state.clone(), limit_auth))
+            .with_state(state);
+
+        let signin = |ip: &str, email: &str| {
+            Request::post("/signin")
+                .extension(ConnectInfo(SocketAddr::new(ip.parse().unwrap(), 443)))
+                .body(Body::from(format!(
+                    r#"{{"email":"{email}","password":"x"}}"#
+                )))
+        };
+
+        let res = app
+            .clone()
+            .oneshot(signin("10.0.0.1", "tchen@acme.org")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // same ip
+        let res = app
+            .clone()
+            .oneshot(signin("10.0.0.1", "alice@acme.org")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.headers().get(RETRY_AFTER).is_some());
+
+        // same email from another ip
+        let res = app
+            .clone()
+            .oneshot(signin("10.0.0.2", "TChen@acme.org")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let res = app.oneshot(signin("10.0.0.3", "bob@acme.org")?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn limit_auth_should_ignore_spoofed_forwarded_for() -> anyhow::Result<()> {
+        let (_tdb, mut state) = AppState::try_new_test().await?;
+        state.auth_limiter = ReloadableLimiter::new(Some(&RateLimitConfig {
+            burst: 1,
+            per_minute: 1,
+        }));
+        let state = Arc::new(state);
+        let app = Router::new()
+            .route("/signin", post(test_handler))
+            .layer(from_fn_with_state(state.clone(), limit_auth))
+            .with_state(state);
+
+        // a new forwarded ip on every request from the same untrusted peer
+        let peer = ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 443)));
+        for (i, status) in [StatusCode::OK, StatusCode::TOO_MANY_REQUESTS]
+            .into_iter()
+            .enumerate()
+        {
+            let req = Request::post("/signin")
+                .extension(peer)
+                .header(FORWARDED_FOR_HEADER, format!("192.168.0.{i}"))
+                .body(Body::empty())?;
+            assert_eq!(app.clone().oneshot(req).await?.status(), status);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn client_ip_should_only_trust_configured_proxies() {
+        let proxy: IpAddr = [10, 0, 0, 1].into();
+        let client: IpAddr = [203, 0, 113, 7].into();
+        let mut headers = HeaderMap::new();
+        // the client forged the first entry, the proxy appended the real address
+        headers.insert(
+            FORWARDED_FOR_HEADER,
+            HeaderValue::from_static("1.2.3.4, 203.0.113.7, 10.0.0.1"),
+        );
+
+        assert_eq!(client_ip(proxy, &headers, &[]), proxy);
+        assert_eq!(client_ip(client, &headers, &[proxy]), client);
+        assert_eq!(client_ip(proxy, &headers, &[proxy]), client);
+
+        headers.insert(FORWARDED_FOR_HEADER, HeaderValue::from_static("unknown"));
+        assert_eq!(client_ip(proxy, &headers, &[proxy]), proxy);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_layer_should_work() -> anyhow::Result<()> {
+        let config = RateLimitConfig {
+            burst: 2,
+            per_minute: 1,
+        };
+        let app = Router::new()
+            .route("/", get(test_handler))
+            .layer(RateLimitLayer::new(Arc::new(ReloadableLimiter::new(Some(
+                &config,
+            )))))
+            .layer(from_fn(|mut req: Request, next: Next| async move {
+                let id = req.headers()["x-user-id"]
+                    .to_str()
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                req.extensions_mut()
+                    .insert(User::new(id, "Tyr Chen", "tchen@acme.org"));
+                next.run(req).await
+            }));
+        let req = |id: i64| {
+            Request::get("/")
+                .header("x-user-id", id)
+                .body(Body::empty())
+        };
+
+        let res = app.clone().oneshot(req(1)?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[RATE_LIMIT_LIMIT_HEADER], "2");
+        assert_eq!(res.headers()[RATE_LIMIT_REMAINING_HEADER], "1");
+
+        let res = app.clone().oneshot(req(1)?).await?;
+        assert_eq!(res.headers()[RATE_LIMIT_REMAINING_HEADER], "0");
+
+        let res = app.clone().oneshot(req(1)?).await?;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.headers().get(RETRY_AFTER).is_some());
+        assert!(res.headers().get(RATE_LIMIT_RESET_HEADER).is_some());
+        let body = http_body_util::BodyExt::collect(res.into_body()).await?;
+        let err: crate::ErrorOutput = serde_json::from_slice(&body.to_bytes())?;
+        assert_eq!(err.code, "too_many_requests");
+
+        // other users have their own bucket
+        let res = app.oneshot(req(2)?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use axum::{extract::Request, http::HeaderValue, middleware::Next, response::Response};
-use tracing::warn;
+use chat_core::REQUEST_ID;
+use tracing::{warn, Span};
 
 use super::REQUEST_ID_HEADER;
 use uuid::Uuid;
@@ -20,7 +21,13 @@ pub async fn set_request_id(mut req: Request, next: Next) -> Response {
             }
         }
     };
-    let mut res = next.run(req).await;
+    let mut res = match id.as_ref().and_then(|id| id.to_str().ok()) {
+        Some(scoped) => {
+            Span::current().record("request_id", scoped);
+            REQUEST_ID.scope(scoped.to_string(), next.run(req)).await
+        }
+        None => next.run(req).await,
+    };
     if let Some(id) = id {
         if let Err(e) = res.headers_mut().try_insert(REQUEST_ID_HEADER, id.clone()) {
             warn!("set_request_id failed, value {id:?}, error {e}")
@@ -32,10 +39,14 @@ pub async fn set_request_id(mut req: Request, next: Next) -> Response {
 #[cfg(test)]
 mod tests {
     use axum::{body::Body, extract::Request, middleware::from_fn, routing::get, Router};
+    use http_body_util::BodyExt;
     use hyper::StatusCode;
     use tower::ServiceExt;
 
-    use crate::middlewares::{set_request_id, test_handler, REQUEST_ID_HEADER};
+    use crate::{
+        middlewares::{set_request_id, test_handler, REQUEST_ID_HEADER},
+        AppError, ErrorOutput,
+    };
 
     #[tokio::test]
     async fn set_request_id_should_work() -> anyhow::Result<()> {
@@ -50,4 +61,23 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn error_output_should_echo_request_id() -> anyhow::Result<()> {
+        let app = Router::new()
+            .route("/", get(|| async { AppError::UserNotFound(1) }))
+            .layer(from_fn(set_request_id));
+
+        let req = Request::builder()
+            .uri("/")
+            .header(REQUEST_ID_HEADER, "req-1")
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let body = res.into_body().collect().await?.to_bytes();
+        let ret: ErrorOutput = serde_json::from_slice(&body)?;
+        assert_eq!(ret.code, "user_not_found");
+        assert_eq!(ret.request_id.as_deref(), Some("req-1"));
+        Ok(())
+    }
 }
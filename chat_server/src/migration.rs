@@ -0,0 +1,199 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    migrate::{Migrate, Migrator},
+    PgPool,
+};
+use tracing::{info, warn};
+
+/// the migrations of `/migrations`, embedded at build time
+pub static MIGRATOR: Migrator = sqlx::migrate!("../migrations");
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MigrateConfig {
+    /// apply the pending migrations at startup
+    #[serde(default)]
+    pub auto: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationStatus {
+    pub version: i64,
+    /// none for a migration applied by a newer binary
+    pub description: Option<String>,
+    pub applied: bool,
+}
+
+/// apply the pending migrations, return the versions applied
+pub async fn migrate_up(pool: &PgPool) -> Result<Vec<i64>> {
+    apply(&MIGRATOR, pool).await
+}
+
+/// the embedded migrations and the ones the database has which this binary doesn't know
+pub async fn migration_status(pool: &PgPool) -> Result<Vec<MigrationStatus>> {
+    status_of(&MIGRATOR, pool).await
+}
+
+async fn apply(migrator: &Migrator, pool: &PgPool) -> Result<Vec<i64>> {
+    let before = applied_versions(pool).await?;
+    migrator.run(pool).await?;
+    let after = applied_versions(pool).await?;
+    let mut applied: Vec<_> = after.difference(&before).copied().collect();
+    applied.sort();
+    Ok(applied)
+}
+
+async fn status_of(migrator: &Migrator, pool: &PgPool) -> Result<Vec<MigrationStatus>> {
+    let applied = applied_versions(pool).await?;
+    let mut status: Vec<_> = migrator
+        .iter()
+        .map(|m| MigrationStatus {
+            version: m.version,
+            description: Some(m.description.to_string()),
+            applied: applied.contains(&m.version),
+        })
+        .collect();
+    let known: HashSet<_> = migrator.iter().map(|m| m.version).collect();
+    status.extend(applied.difference(&known).map(|&version| MigrationStatus {
+        version,
+        description: None,
+        applied: true,
+    }));
+    status.sort_by_key(|s| s.version);
+    Ok(status)
+}
+
+/// refuse a schema newer than this binary, then apply the pending migrations if `auto` is set
+pub async fn prepare_schema(pool: &PgPool, config: &MigrateConfig) -> Result<()> {
+    let status = migration_status(pool).await?;
+    if let Some(unknown) = status.iter().find(|s| s.description.is_none()) {
+        bail!(
+            "database schema version {} is newer than this binary supports ({}), upgrade chat_server",
+            unknown.version,
+            latest_version()
+        );
+    }
+
+    let pending = status.iter().filter(|s| !s.applied).count();
+    if pending == 0 {
+        return Ok(());
+    }
+    if config.auto {
+        for version in migrate_up(pool).await? {
+            info!(version, "migration applied");
+        }
+    } else {
+        warn!(
+            pending,
+            "database schema is behind, run `chat_server migrate up` or set migrate.auto"
+        );
+    }
+    Ok(())
+}
+
+/// version of the last embedded migration
+pub fn latest_version() -> i64 {
+    MIGRATOR.iter().map(|m| m.version).max().unwrap_or_default()
+}
+
+/// the embedded migrations as a source for the test databases
+#[cfg(test)]
+#[derive(Debug)]
+pub(crate) struct EmbeddedMigrations;
+
+#[cfg(test)]
+impl sqlx::migrate::MigrationSource<'static> for EmbeddedMigrations {
+    fn resolve(
+        self,
+    ) -> futures_util::future::BoxFuture<
+        'static,
+        Result<Vec<sqlx::migrate::Migration>, sqlx::error::BoxDynError>,
+    > {
+        Box::pin(async { Ok(MIGRATOR.iter().cloned().collect()) })
+    }
+}
+
+async fn applied_versions(pool: &PgPool) -> Result<HashSet<i64>> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    if let Some(version) = conn.dirty_version().await? {
+        bail!("migration {version} is partially applied, fix the database manually");
+    }
+    let applied = conn.list_applied_migrations().await?;
+    Ok(applied.into_iter().map(|m| m.version).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppState;
+
+    #[tokio::test]
+    async fn migration_status_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let status = migration_status(&state.pool).await?;
+        assert_eq!(status.len(), MIGRATOR.iter().count());
+        assert!(status.iter().all(|s| s.applied));
+        assert!(migrate_up(&state.pool).await?.is_empty());
+        prepare_schema(&state.pool, &MigrateConfig { auto: true }).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn apply_should_run_pending_migrations() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let dir = std::env::temp_dir().join(format!("migrations-{}", nanoid::nanoid!(8)));
+        tokio::fs::create_dir_all(&dir).await?;
+        tokio::fs::write(dir.join("1_first.sql"), "CREATE TABLE first (id int);").await?;
+        // the migrations of the test database are not in this migrator
+        let migrator = |dir| async move {
+            let mut migrator = Migrator::new(dir).await?;
+            migrator.set_ignore_missing(true);
+            anyhow::Ok(migrator)
+        };
+
+        assert_eq!(
+            apply(&migrator(dir.clone()).await?, &state.pool).await?,
+            [1]
+        );
+
+        tokio::fs::write(dir.join("2_second.sql"), "CREATE TABLE second (id int);").await?;
+        let migrator = migrator(dir.clone()).await?;
+        let status = status_of(&migrator, &state.pool).await?;
+        let version = |v| status.iter().find(|s| s.version == v).unwrap();
+        assert!(version(1).applied);
+        assert!(!version(2).applied);
+        assert_eq!(version(2).description.as_deref(), Some("second"));
+
+        assert_eq!(apply(&migrator, &state.pool).await?, [2]);
+        assert!(apply(&migrator, &state.pool).await?.is_empty());
+        tokio::fs::remove_dir_all(dir).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prepare_schema_should_refuse_newer_schema() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let version = latest_version() + 1;
+        sqlx::query(
+            r#"
+            INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+            VALUES ($1, 'from the future', true, '\x00', 0)
+            "#,
+        )
+        .bind(version)
+        .execute(&state.pool)
+        .await?;
+
+        let status = migration_status(&state.pool).await?;
+        assert_eq!(status.last().unwrap().description, None);
+        let err = prepare_schema(&state.pool, &MigrateConfig { auto: true })
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(&format!("schema version {version} is newer")));
+        Ok(())
+    }
+}
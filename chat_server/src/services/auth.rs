@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::{AppError, AppState};
+
+impl AppState {
+    pub async fn add_signin_attempt(
+        &self,
+        email: &str,
+        ip: &str,
+        success: bool,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO signin_attempts (email, ip, success)
+            VALUES ($1, $2, $3)
+            "#,
+        )
+        .bind(email.to_lowercase())
+        .bind(ip)
+        .bind(success)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// how long the email is still locked out for, the lockout doubles with every
+    /// failure beyond `max_failures` since the last successful signin
+    pub async fn signin_lockout(&self, email: &str) -> Result<Option<Duration>, AppError> {
+        let Some(lockout) = &self.auth.lockout else {
+            return Ok(None);
+        };
+
+        let (failures, last_failure): (i64, Option<DateTime<Utc>>) = sqlx::query_as(
+            r#"
+            SELECT COUNT(*), MAX(created_at)
+            FROM signin_attempts
+            WHERE email = $1 AND NOT success
+            AND created_at > COALESCE(
+                (SELECT MAX(created_at) FROM signin_attempts WHERE email = $1 AND success),
+                '-infinity'
+            )
+            "#,
+        )
+        .bind(email.to_lowercase())
+        .fetch_one(&self.pool)
+        .await?;
+
+        let (Some(last_failure), Some(exceeded)) = (
+            last_failure,
+            (failures as u64).checked_sub(lockout.max_failures as u64),
+        ) else {
+            return Ok(None);
+        };
+
+        let secs = lockout
+            .base
+            .saturating_mul(1 << exceeded.min(32))
+            .min(lockout.max);
+        let until = last_failure + Duration::from_secs(secs);
+        Ok((until - Utc::now()).to_std().ok())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::AppState;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn signin_lockout_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let email = "tchen@acme.org";
+        let max_failures = state.auth.lockout.as_ref().unwrap().max_failures;
+
+        for _ in 1..max_failures {
+            state.add_signin_attempt(email, "127.0.0.1", false).await?;
+        }
+        assert!(state.signin_lockout(email).await?.is_none());
+
+        state.add_signin_attempt(email, "127.0.0.1", false).await?;
+        let first = state.signin_lockout("TChen@acme.org").await?.unwrap();
+
+        state.add_signin_attempt(email, "127.0.0.1", false).await?;
+        let second = state.signin_lockout(email).await?.unwrap();
+        assert!(second > first);
+
+        // a successful signin resets the failures
+        state.add_signin_attempt(email, "127.0.0.1", true).await?;
+        assert!(state.signin_lockout(email).await?.is_none());
+        Ok(())
+    }
+}
@@ -1,29 +1,20 @@
 use std::collections::HashSet;
 
 use crate::{AppError, AppState};
-use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use utoipa::ToSchema;
+use validator::Validate;
 
-use super::ChatType;
+use super::{set_trace_context, Chat, ChatType};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate, ToSchema)]
 pub struct CreateChat {
+    #[validate(length(min = 1, max = 64, message = "must be 1 to 64 characters"))]
     pub name: Option<String>,
     pub members: HashSet<i64>,
     pub public: bool,
 }
 
-#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
-pub struct Chat {
-    pub id: i64,
-    pub ws_id: i64,
-    pub name: Option<String>,
-    pub r#type: ChatType,
-    pub members: Vec<i64>,
-    pub created_at: DateTime<Utc>,
-}
-
 impl AppState {
     pub async fn create_chat(&self, input: &CreateChat, ws_id: i64) -> Result<i64, AppError> {
         let members: Vec<i64> = input.members.iter().cloned().collect();
@@ -74,6 +65,8 @@ impl AppState {
             }
         };
 
+        let mut tx = self.pool.begin().await?;
+        set_trace_context(&mut tx).await?;
         let (chat_id,): (i64,) = sqlx::query_as(
             r#"
             INSERT INTO chats (ws_id, name, type, members)
@@ -84,8 +77,9 @@ impl AppState {
         .bind(&input.name)
         .bind(chat_type)
         .bind(&members)
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
+        tx.commit().await?;
 
         Ok(chat_id)
     }
@@ -99,7 +93,7 @@ impl AppState {
             "#,
         )
         .bind(ws_id as i64)
-        .fetch_all(&self.pool)
+        .fetch_all(&self.read_pool)
         .await?;
 
         Ok(chats)
@@ -136,6 +130,9 @@ mod test {
     use std::collections::HashSet;
 
     use anyhow::Result;
+    use sqlx::postgres::PgListener;
+
+    use chat_core::REQUEST_ID;
 
     use crate::{services::CreateChat, AppError, AppState};
 
@@ -148,6 +145,25 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn create_chat_should_notify_with_request_id() -> Result<()> {
+        let (tdb, state) = AppState::try_new_test().await?;
+        let mut listener = PgListener::connect(&tdb.url()).await?;
+        listener.listen("chat_updated").await?;
+
+        let create_chat = CreateChat::new(None, HashSet::from([1, 2, 3]), true);
+        REQUEST_ID
+            .scope("request-1".to_string(), state.create_chat(&create_chat, 1))
+            .await?;
+
+        let notif = listener.recv().await?;
+        let payload: serde_json::Value = serde_json::from_str(notif.payload())?;
+        assert_eq!(payload["request_id"], "request-1");
+        // no span is sampled outside of a traced request
+        assert!(payload["traceparent"].is_null());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn create_chat_should_fail_because_99() -> Result<()> {
         let (_tdb, state) = AppState::try_new_test().await?;
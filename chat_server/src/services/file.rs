@@ -0,0 +1,337 @@
+use std::{sync::Arc, time::Duration};
+
+use sqlx::PgConnection;
+use tokio::fs;
+use tracing::{info, warn};
+
+use crate::{handlers::ChatFile, AppError, AppState};
+
+/// files past the grace period `$1` neither attached to a message nor used as avatar
+const UNATTACHED_FILES: &str = r#"
+    f.created_at < NOW() - make_interval(secs => $1)
+    AND NOT EXISTS(SELECT 1 FROM messages m WHERE m.files @> ARRAY[f.url])
+    AND NOT EXISTS(SELECT 1 FROM users u WHERE u.avatar_url = f.url)
+"#;
+
+impl AppState {
+    /// uploading the same file again restarts its gc grace period
+    pub async fn add_file(
+        &self,
+        ws_id: i64,
+        url: &str,
+        uploader_id: i64,
+        size: i64,
+    ) -> Result<(), AppError> {
+        let mut conn = self.pool.acquire().await?;
+        upsert_file(&mut conn, ws_id, url, uploader_id, size).await
+    }
+
+    /// record the upload and write the file unless it's on disk already. The url is locked
+    /// like by `gc_files`, so a sweep can't remove the file of the row
+    pub async fn store_file(
+        &self,
+        chat_file: &ChatFile,
+        uploader_id: i64,
+        data: &[u8],
+    ) -> Result<(), AppError> {
+        let url = chat_file.url();
+        let mut tx = self.pool.begin().await?;
+        lock_file_url(&mut tx, &url).await?;
+        upsert_file(&mut tx, chat_file.ws_id, &url, uploader_id, data.len() as _).await?;
+
+        let path = chat_file.path(&self.file.base_dir);
+        if fs::try_exists(&path).await? {
+            warn!(url, ?path, "file already exists");
+        } else {
+            fs::create_dir_all(path.parent().expect("file path parent should exists")).await?;
+            fs::write(&path, data).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn is_file_uploader(&self, url: &str, user_id: i64) -> Result<bool, AppError> {
+        let (is_uploader,): (bool,) = sqlx::query_as(
+            "SELECT EXISTS(SELECT 1 FROM files WHERE url = $1 AND uploader_id = $2)",
+        )
+        .bind(url)
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(is_uploader)
+    }
+
+    /// bytes stored by the workspace, the same file uploaded twice is counted once
+    pub async fn workspace_storage(&self, ws_id: i64) -> Result<i64, AppError> {
+        let (size,): (i64,) = sqlx::query_as(
+            r#"
+            SELECT COALESCE(SUM(size), 0)::bigint
+            FROM (SELECT DISTINCT ON (url) size FROM files WHERE ws_id = $1) f
+            "#,
+        )
+        .bind(ws_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(size)
+    }
+
+    /// remove files neither attached to a message nor used as avatar after the grace period,
+    /// return the urls of the files deleted from disk
+    pub async fn gc_files(&self, grace: Duration) -> Result<Vec<String>, AppError> {
+        let grace = grace.as_secs_f64();
+        // candidates, checked again under the lock of each url
+        let urls: Vec<(String,)> = sqlx::query_as(&format!(
+            "SELECT DISTINCT url FROM files f WHERE {UNATTACHED_FILES}"
+        ))
+        .bind(grace)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut removed = Vec::with_capacity(urls.len());
+        for (url,) in urls {
+            let mut tx = self.pool.begin().await?;
+            lock_file_url(&mut tx, &url).await?;
+            let deleted = sqlx::query(&format!(
+                "DELETE FROM files f WHERE f.url = $2 AND {UNATTACHED_FILES}"
+            ))
+            .bind(grace)
+            .bind(&url)
+            .execute(&mut *tx)
+            .await?;
+            // a url is kept on disk while another uploader's row is still in its grace period
+            let (in_use,): (bool,) =
+                sqlx::query_as("SELECT EXISTS(SELECT 1 FROM files WHERE url = $1)")
+                    .bind(&url)
+                    .fetch_one(&mut *tx)
+                    .await?;
+            if deleted.rows_affected() > 0 && !in_use && self.remove_stored_file(&url).await {
+                removed.push(url);
+            }
+            tx.commit().await?;
+        }
+
+        Ok(removed)
+    }
+
+    /// remove the file of the url and its thumbnails, false if the url is invalid
+    async fn remove_stored_file(&self, url: &str) -> bool {
+        let chat_file: ChatFile = match url.parse() {
+            Ok(chat_file) => chat_file,
+            Err(e) => {
+                warn!(url, "gc skip invalid file url: {e}");
+                return false;
+            }
+        };
+
+        let base_dir = &self.file.base_dir;
+        let thumbnails = self
+            .file
+            .thumbnails
+            .iter()
+            .map(|t| chat_file.thumbnail_path(base_dir, &t.name));
+        for path in std::iter::once(chat_file.path(base_dir)).chain(thumbnails) {
+            if let Err(e) = fs::remove_file(&path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!(?path, "gc failed to remove file: {e}");
+                }
+            }
+        }
+        true
+    }
+
+    /// periodically remove orphaned files if `file.gc` is configured
+    pub fn spawn_file_gc(self: Arc<Self>) {
+        let Some(gc) = self.file.gc.clone() else {
+            return;
+        };
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(gc.interval));
+            loop {
+                interval.tick().await;
+                match self.gc_files(Duration::from_secs(gc.grace)).await {
+                    Ok(urls) if !urls.is_empty() => {
+                        info!(files = urls.len(), "file gc removed files")
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("file gc failed: {e}"),
+                }
+            }
+        });
+    }
+
+    /// a file is readable by members of the chats whose messages reference it,
+    /// or by its uploader as long as it is not attached to any message.
+    /// an avatar is readable by everyone in the workspace of its user
+    pub async fn can_access_file(&self, url: &str, user_id: i64) -> Result<bool, AppError> {
+        let (can_access,): (bool,) = sqlx::query_as(
+            r#"
+            SELECT EXISTS(
+                SELECT 1
+                FROM messages m
+                JOIN chats c ON c.id = m.chat_id
+                WHERE m.files @> ARRAY[$1] AND $2 = ANY(c.members)
+            ) OR (
+                EXISTS(SELECT 1 FROM files WHERE url = $1 AND uploader_id = $2)
+                AND NOT EXISTS(SELECT 1 FROM messages WHERE files @> ARRAY[$1])
+            ) OR EXISTS(
+                SELECT 1
+                FROM users a
+                JOIN users u ON u.ws_id = a.ws_id
+                WHERE a.avatar_url = $1 AND u.id = $2
+            )
+            "#,
+        )
+        .bind(url)
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(can_access)
+    }
+}
+
+/// uploads and gc sweeps of the same url wait for each other until the transaction ends
+async fn lock_file_url(conn: &mut PgConnection, url: &str) -> Result<(), AppError> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(url)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+async fn upsert_file(
+    conn: &mut PgConnection,
+    ws_id: i64,
+    url: &str,
+    uploader_id: i64,
+    size: i64,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO files (url, ws_id, uploader_id, size)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (url, uploader_id) DO UPDATE SET created_at = NOW()
+        "#,
+    )
+    .bind(url)
+    .bind(ws_id)
+    .bind(uploader_id)
+    .bind(size)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use super::lock_file_url;
+    use crate::{handlers::ChatFile, AppState};
+    use anyhow::Result;
+    use tokio::fs;
+
+    #[tokio::test]
+    async fn can_access_file_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let url = "/files/1/0a0/a9f/2a6772942557ab5355d76af442f8f65e01.txt";
+
+        // attached to private chat 2 with members 1, 2, 3
+        assert!(state.can_access_file(url, 1).await?);
+        assert!(state.can_access_file(url, 3).await?);
+        assert!(!state.can_access_file(url, 4).await?);
+
+        // not attached yet, only the uploader can read it
+        let url = "/files/1/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed.txt";
+        state.add_file(1, url, 4, 11).await?;
+        assert!(state.can_access_file(url, 4).await?);
+        assert!(!state.can_access_file(url, 1).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn workspace_storage_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        assert_eq!(state.workspace_storage(1).await?, 197689 + 13);
+
+        // the same file uploaded by another user is counted once
+        let url = "/files/1/0a0/a9f/2a6772942557ab5355d76af442f8f65e01.txt";
+        state.add_file(1, url, 2, 13).await?;
+        assert_eq!(state.workspace_storage(1).await?, 197689 + 13);
+        assert_eq!(state.workspace_storage(2).await?, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gc_files_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let url = "/files/1/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed.txt";
+        state.add_file(1, url, 4, 11).await?;
+
+        // still in grace period
+        let removed = state.gc_files(Duration::from_secs(3600)).await?;
+        assert!(removed.is_empty());
+
+        // attached files are kept
+        let removed = state.gc_files(Duration::ZERO).await?;
+        assert_eq!(removed, [url]);
+        assert!(!state.can_access_file(url, 4).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gc_files_should_keep_reuploaded_file() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let url = "/files/1/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed.txt";
+        state.add_file(1, url, 4, 11).await?;
+        sqlx::query("UPDATE files SET created_at = NOW() - INTERVAL '2 hours' WHERE url = $1")
+            .bind(url)
+            .execute(&state.pool)
+            .await?;
+
+        // uploaded again by the same user, a new grace period starts
+        state.add_file(1, url, 4, 11).await?;
+        let removed = state.gc_files(Duration::from_secs(3600)).await?;
+        assert!(removed.is_empty());
+        assert!(state.can_access_file(url, 4).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn store_file_should_wait_for_gc_of_the_url() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let state = Arc::new(state);
+        let data: &'static [u8] = b"uploaded while the gc sweeps it";
+        let chat_file = ChatFile::new(1, "sweep.txt", data);
+        let url = chat_file.url();
+        let path = chat_file.path(&state.file.base_dir);
+        state.store_file(&chat_file, 1, data).await?;
+        assert!(path.exists());
+
+        // a sweep holding the url removes the row and the file
+        let mut tx = state.pool.begin().await?;
+        lock_file_url(&mut tx, &url).await?;
+        sqlx::query("DELETE FROM files WHERE url = $1")
+            .bind(&url)
+            .execute(&mut *tx)
+            .await?;
+        let upload = tokio::spawn({
+            let state = state.clone();
+            let chat_file = chat_file.clone();
+            async move { state.store_file(&chat_file, 1, data).await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!upload.is_finished());
+        fs::remove_file(&path).await?;
+        tx.commit().await?;
+
+        // the upload writes the file again once the sweep is done
+        upload.await??;
+        assert!(path.exists());
+        assert!(state.is_file_uploader(&url, 1).await?);
+        Ok(())
+    }
+}
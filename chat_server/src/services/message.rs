@@ -1,10 +1,19 @@
-use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use utoipa::IntoParams;
+use validator::Validate;
 
+use super::Message;
 use crate::{AppError, AppState};
 
 impl AppState {
+    /// rebuild the indexes of messages, e.g. the gin index of their files, after bulk changes
+    pub async fn reindex_messages(&self) -> Result<(), AppError> {
+        sqlx::query("REINDEX TABLE messages")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
     pub async fn list_messages(
         &self,
         input: ListMessages,
@@ -25,25 +34,17 @@ impl AppState {
         .bind(chat_id as i64)
         .bind(last_id as i64)
         .bind(input.limit as i64)
-        .fetch_all(&self.pool)
+        .fetch_all(&self.read_pool)
         .await?;
 
         Ok(messages)
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ListMessages {
     pub last_id: Option<u64>,
+    #[validate(range(min = 1, max = 100, message = "must be 1 to 100"))]
     pub limit: u64,
 }
-
-#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
-pub struct Message {
-    pub id: i64,
-    pub chat_id: i64,
-    pub sender_id: i64,
-    pub content: String,
-    pub files: Vec<String>,
-    pub created_at: DateTime<Utc>,
-}
@@ -1,19 +1,30 @@
+mod auth;
 mod chat;
+mod file;
 mod message;
+mod token;
 mod user;
 mod workspace;
-use serde::{Deserialize, Serialize};
-
 pub use chat::*;
 pub use message::*;
+pub use token::*;
 pub use user::*;
+pub use workspace::Workspace;
+
+pub use chat_core::{Chat, ChatType, Message, User};
+
+use chat_core::{current_request_id, current_traceparent};
+use sqlx::PgConnection;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, PartialOrd, sqlx::Type)]
-#[sqlx(type_name = "chat_type", rename_all = "snake_case")]
-#[serde(rename_all = "snake_case")]
-pub enum ChatType {
-    Single,
-    Group,
-    PrivateChannel,
-    PublicChannel,
+/// pass the request id and `traceparent` to the triggers of the transaction so notify_server
+/// can continue the trace of the notifications
+pub(crate) async fn set_trace_context(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "SELECT set_config('chat.request_id', $1, true), set_config('chat.traceparent', $2, true)",
+    )
+    .bind(current_request_id().unwrap_or_default())
+    .bind(current_traceparent().unwrap_or_default())
+    .execute(conn)
+    .await?;
+    Ok(())
 }
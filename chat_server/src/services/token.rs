@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{AppError, AppState};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "user_token_kind", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum UserTokenKind {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl AppState {
+    /// create a single use token, only its hash is stored
+    pub async fn create_user_token(
+        &self,
+        user_id: i64,
+        kind: UserTokenKind,
+        ttl: Duration,
+    ) -> Result<String, AppError> {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = hex::encode(bytes);
+        let expires_at = Utc::now() + ttl;
+
+        sqlx::query(
+            r#"
+            INSERT INTO user_tokens (token_hash, user_id, kind, expires_at)
+            VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(hash_token(&token))
+        .bind(user_id)
+        .bind(kind)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// mark the token used and return its user id, fail if it is unknown, used or expired
+    pub async fn consume_user_token(
+        &self,
+        token: &str,
+        kind: UserTokenKind,
+    ) -> Result<i64, AppError> {
+        let user_id: Option<(i64,)> = sqlx::query_as(
+            r#"
+            UPDATE user_tokens
+            SET used_at = NOW()
+            WHERE token_hash = $1 AND kind = $2 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING user_id
+            "#,
+        )
+        .bind(hash_token(token))
+        .bind(kind)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match user_id {
+            Some((user_id,)) => Ok(user_id),
+            None => Err(AppError::InvalidToken(
+                "token is invalid or expired".to_string(),
+            )),
+        }
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use anyhow::Result;
+
+    use super::UserTokenKind;
+    use crate::{AppError, AppState};
+
+    #[tokio::test]
+    async fn user_token_should_be_single_use() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let kind = UserTokenKind::ResetPassword;
+        let token = state
+            .create_user_token(1, kind, Duration::from_secs(60))
+            .await?;
+
+        // wrong kind
+        let ret = state
+            .consume_user_token(&token, UserTokenKind::VerifyEmail)
+            .await;
+        assert!(matches!(ret, Err(AppError::InvalidToken(_))));
+
+        assert_eq!(state.consume_user_token(&token, kind).await?, 1);
+        let ret = state.consume_user_token(&token, kind).await;
+        assert!(matches!(ret, Err(AppError::InvalidToken(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_user_token_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let kind = UserTokenKind::VerifyEmail;
+        let token = state.create_user_token(1, kind, Duration::ZERO).await?;
+        let ret = state.consume_user_token(&token, kind).await;
+        assert!(matches!(ret, Err(AppError::InvalidToken(_))));
+        Ok(())
+    }
+}
@@ -2,21 +2,27 @@ use argon2::PasswordHash;
 use argon2::PasswordVerifier;
 use serde::{Deserialize, Serialize};
 
+use std::time::Duration;
+
+use crate::handlers::ChatFile;
+use crate::mailer::Mail;
 use crate::AppError;
 use crate::AppState;
 
+use super::{User, UserTokenKind};
+
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
     Argon2,
 };
 
-use chrono::{DateTime, Utc};
-use sqlx::prelude::FromRow;
+use utoipa::ToSchema;
+use validator::Validate;
 
 impl AppState {
     pub async fn find_user_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
         let user = sqlx::query_as(
-            "SELECT id, ws_id, fullname, email, created_at FROM users WHERE email = $1",
+            "SELECT id, ws_id, fullname, email, avatar_url, token_version, created_at FROM users WHERE email = $1",
         )
         .bind(email)
         .fetch_optional(&self.pool)
@@ -24,6 +30,16 @@ impl AppState {
         Ok(user)
     }
 
+    pub async fn find_user_by_id(&self, id: i64) -> Result<Option<User>, AppError> {
+        let user = sqlx::query_as(
+            "SELECT id, ws_id, fullname, email, avatar_url, token_version, created_at FROM users WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(user)
+    }
+
     pub async fn create_user(&self, input: &CreateUser) -> Result<User, AppError> {
         // check if email exists
         let user = self.find_user_by_email(&input.email).await?;
@@ -42,7 +58,7 @@ impl AppState {
             r#"
             INSERT INTO users (ws_id, email, fullname, password_hash)
             VALUES ($1, $2, $3, $4)
-            RETURNING id, ws_id, fullname, email, created_at
+            RETURNING id, ws_id, fullname, email, avatar_url, token_version, created_at
             "#,
         )
         .bind(ws.id)
@@ -61,7 +77,7 @@ impl AppState {
     }
 
     pub async fn verify_user(&self, input: &SigninUser) -> Result<Option<User>, AppError> {
-        let user: Option<User> = sqlx::query_as("SELECT id, ws_id, fullname, email, password_hash, created_at FROM users WHERE email = $1")
+        let user: Option<User> = sqlx::query_as("SELECT id, ws_id, fullname, email, avatar_url, token_version, password_hash, created_at FROM users WHERE email = $1 AND disabled_at IS NULL")
             .bind(&input.email)
             .fetch_optional(&self.pool)
             .await?;
@@ -77,6 +93,202 @@ impl AppState {
         }
         Ok(None)
     }
+
+    /// mail a verification token to a newly signed up user
+    pub async fn send_verify_email(&self, user: &User) -> Result<(), AppError> {
+        let ttl = Duration::from_secs(self.auth.verify_token_ttl);
+        let token = self
+            .create_user_token(user.id, UserTokenKind::VerifyEmail, ttl)
+            .await?;
+        let body = format!(
+            "Hi {},\n\nUse the token below to verify your email, it expires in {} minutes.\n\n{}\n",
+            user.fullname,
+            ttl.as_secs() / 60,
+            token
+        );
+        self.send_mail(&user.email, "Verify your email", body).await
+    }
+
+    pub async fn verify_email(&self, input: &VerifyEmail) -> Result<(), AppError> {
+        let user_id = self
+            .consume_user_token(&input.token, UserTokenKind::VerifyEmail)
+            .await?;
+        sqlx::query("UPDATE users SET verified_at = NOW() WHERE id = $1 AND verified_at IS NULL")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// mail a reset token, unknown emails are ignored so they can't be probed
+    pub async fn forgot_password(&self, input: &ForgotPassword) -> Result<(), AppError> {
+        let Some(user) = self.find_user_by_email(&input.email).await? else {
+            return Ok(());
+        };
+
+        let ttl = Duration::from_secs(self.auth.reset_token_ttl);
+        let token = self
+            .create_user_token(user.id, UserTokenKind::ResetPassword, ttl)
+            .await?;
+        let body = format!(
+            "Hi {},\n\nUse the token below to reset your password, it expires in {} minutes.\n\n{}\n",
+            user.fullname,
+            ttl.as_secs() / 60,
+            token
+        );
+        self.send_mail(&user.email, "Reset your password", body)
+            .await
+    }
+
+    pub async fn reset_password(&self, input: &ResetPassword) -> Result<(), AppError> {
+        let user_id = self
+            .consume_user_token(&input.token, UserTokenKind::ResetPassword)
+            .await?;
+        self.set_password(user_id, &input.password).await
+    }
+
+    /// replace the password without the old one, e.g. by an admin, and revoke the issued tokens
+    pub async fn set_password(&self, user_id: i64, password: &str) -> Result<(), AppError> {
+        let password_hash = hash_password(password)?;
+        let updated = sqlx::query(
+            "UPDATE users SET password_hash = $1, token_version = token_version + 1 WHERE id = $2",
+        )
+        .bind(password_hash)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(AppError::UserNotFound(user_id));
+        }
+
+        // other reset tokens are void once the password changed
+        sqlx::query(
+            r#"
+            UPDATE user_tokens
+            SET used_at = NOW()
+            WHERE user_id = $1 AND kind = $2 AND used_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .bind(UserTokenKind::ResetPassword)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// a token is revoked once the user's token version moved on, e.g. after a password change,
+    /// or the user is disabled
+    pub async fn is_token_current(&self, user: &User) -> Result<bool, AppError> {
+        let version: Option<(i32,)> =
+            sqlx::query_as("SELECT token_version FROM users WHERE id = $1 AND disabled_at IS NULL")
+                .bind(user.id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(version.is_some_and(|(version,)| version == user.token_version))
+    }
+
+    /// a disabled user can't sign in and the issued tokens are revoked
+    pub async fn set_user_disabled(&self, user_id: i64, disabled: bool) -> Result<(), AppError> {
+        let sql = if disabled {
+            "UPDATE users SET disabled_at = NOW(), token_version = token_version + 1 WHERE id = $1"
+        } else {
+            "UPDATE users SET disabled_at = NULL WHERE id = $1"
+        };
+        let updated = sqlx::query(sql).bind(user_id).execute(&self.pool).await?;
+        if updated.rows_affected() == 0 {
+            return Err(AppError::UserNotFound(user_id));
+        }
+        Ok(())
+    }
+
+    pub async fn update_user(&self, user: &User, input: &UpdateUser) -> Result<User, AppError> {
+        if let Some(avatar_url) = &input.avatar_url {
+            self.verify_avatar(user, avatar_url).await?;
+        }
+
+        let updated: Option<User> = sqlx::query_as(
+            r#"
+            UPDATE users
+            SET fullname = COALESCE($2, fullname), avatar_url = COALESCE($3, avatar_url)
+            WHERE id = $1
+            RETURNING id, ws_id, fullname, email, avatar_url, token_version, created_at
+            "#,
+        )
+        .bind(user.id)
+        .bind(&input.fullname)
+        .bind(&input.avatar_url)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        updated.ok_or(AppError::UserNotFound(user.id))
+    }
+
+    /// change password after re-verifying the old one, None if the old password is wrong.
+    /// all issued tokens are revoked, the caller should hand out a new one.
+    pub async fn change_password(
+        &self,
+        user_id: i64,
+        input: &ChangePassword,
+    ) -> Result<Option<User>, AppError> {
+        let password_hash: Option<(String,)> =
+            sqlx::query_as("SELECT password_hash FROM users WHERE id = $1")
+                .bind(user_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some((password_hash,)) = password_hash else {
+            return Err(AppError::UserNotFound(user_id));
+        };
+        if !verify_password(&input.old_password, &password_hash)? {
+            return Ok(None);
+        }
+
+        let password_hash = hash_password(&input.new_password)?;
+        let user = sqlx::query_as(
+            r#"
+            UPDATE users
+            SET password_hash = $2, token_version = token_version + 1
+            WHERE id = $1
+            RETURNING id, ws_id, fullname, email, avatar_url, token_version, created_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(password_hash)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(Some(user))
+    }
+
+    /// avatar must be an image of the user's workspace uploaded by the user. A file merely
+    /// readable through a chat is refused, the avatar would expose it to the whole workspace
+    async fn verify_avatar(&self, user: &User, url: &str) -> Result<(), AppError> {
+        let chat_file: ChatFile = url.parse()?;
+        if chat_file.ws_id != user.ws_id {
+            return Err(AppError::ChatFileError(format!(
+                "avatar {url} doesn't belong to workspace {}",
+                user.ws_id
+            )));
+        }
+        if chat_file.mime().type_() != "image" {
+            return Err(AppError::ChatFileError(format!(
+                "avatar {url} is not an image"
+            )));
+        }
+        if !self.is_file_uploader(url, user.id).await? {
+            return Err(AppError::FileNotFound(url.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn send_mail(&self, to: &str, subject: &str, body: String) -> Result<(), AppError> {
+        let mail = Mail {
+            from: self.mail.from.clone(),
+            to: to.to_string(),
+            subject: subject.to_string(),
+            body,
+        };
+        self.mailer.send(mail).await
+    }
 }
 
 fn hash_password(password: &str) -> Result<String, AppError> {
@@ -105,47 +317,68 @@ fn verify_password(password: &str, password_hash: &str) -> Result<bool, AppError
     Ok(is_valid)
 }
 
-#[derive(Debug, Clone, FromRow, Default, Serialize, Deserialize, PartialEq)]
-pub struct User {
-    pub id: i64,
-    pub ws_id: i64,
-    pub fullname: String,
-    pub email: String,
-    #[sqlx(default)]
-    #[serde(skip)]
-    pub password_hash: Option<String>,
-    pub created_at: DateTime<Utc>,
-}
-
-impl User {
-    pub fn new(id: i64, fullname: &str, email: &str) -> Self {
-        Self {
-            id,
-            ws_id: 0,
-            fullname: fullname.to_string(),
-            email: email.to_string(),
-            password_hash: None,
-            created_at: Utc::now(),
-        }
-    }
-}
-
 /// create a user with email and password
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
 pub struct CreateUser {
     /// Full name of the user
+    #[validate(length(min = 1, max = 64, message = "must be 1 to 64 characters"))]
     pub fullname: String,
     /// Email of the user
+    #[validate(email(message = "must be a valid email"), length(max = 64))]
     pub email: String,
     /// Workspace name - if not exists, create one
+    #[validate(length(min = 1, max = 32, message = "must be 1 to 32 characters"))]
     pub workspace: String,
     /// Password of the user
+    #[validate(length(min = 6, max = 128, message = "must be 6 to 128 characters"))]
     pub password: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
 pub struct SigninUser {
+    #[validate(email(message = "must be a valid email"))]
+    pub email: String,
+    #[validate(length(min = 1, message = "must not be empty"))]
+    pub password: String,
+}
+
+/// fields left out are unchanged
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Validate, ToSchema)]
+pub struct UpdateUser {
+    #[validate(length(min = 1, max = 64, message = "must be 1 to 64 characters"))]
+    pub fullname: Option<String>,
+    /// url of an uploaded image
+    #[validate(length(max = 256))]
+    pub avatar_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct ChangePassword {
+    #[validate(length(min = 1, message = "must not be empty"))]
+    pub old_password: String,
+    #[validate(length(min = 6, max = 128, message = "must be 6 to 128 characters"))]
+    pub new_password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct VerifyEmail {
+    #[validate(length(min = 1, message = "must not be empty"))]
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct ForgotPassword {
+    #[validate(email(message = "must be a valid email"))]
     pub email: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
+pub struct ResetPassword {
+    /// token mailed by forgot password
+    #[validate(length(min = 1, message = "must not be empty"))]
+    pub token: String,
+    /// the new password
+    #[validate(length(min = 6, max = 128, message = "must be 6 to 128 characters"))]
     pub password: String,
 }
 
@@ -173,7 +406,8 @@ impl SigninUser {
 
 #[cfg(test)]
 mod test {
-    use crate::AppState;
+    use super::SigninUser;
+    use crate::{AppError, AppState};
     use anyhow::Result;
 
     #[tokio::test]
@@ -193,4 +427,36 @@ mod test {
         assert!(result.unwrap().is_some());
         Ok(())
     }
+
+    #[tokio::test]
+    async fn set_password_should_revoke_tokens() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        state.set_password(1, "new password").await?;
+
+        assert!(!state.is_token_current(&user).await?);
+        let input = SigninUser::new("tchen@acme.org", "new password");
+        assert!(state.verify_user(&input).await?.is_some());
+        assert!(matches!(
+            state.set_password(99, "new password").await,
+            Err(AppError::UserNotFound(99))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn disabled_user_should_not_sign_in() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let input = SigninUser::new("tchen@acme.org", "123456");
+        let user = state.verify_user(&input).await?.unwrap();
+
+        state.set_user_disabled(1, true).await?;
+        assert!(state.verify_user(&input).await?.is_none());
+        assert!(!state.is_token_current(&user).await?);
+
+        state.set_user_disabled(1, false).await?;
+        let user = state.verify_user(&input).await?.unwrap();
+        assert!(state.is_token_current(&user).await?);
+        Ok(())
+    }
 }
@@ -5,6 +5,20 @@ use sqlx::prelude::FromRow;
 use crate::{AppError, AppState};
 
 impl AppState {
+    pub async fn list_workspaces(&self) -> Result<Vec<Workspace>, AppError> {
+        let workspaces = sqlx::query_as(
+            r#"
+        SELECT id, name, owner_id, created_at
+        FROM workspaces
+        ORDER BY id
+        "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(workspaces)
+    }
+
     pub async fn find_workspace_by_name(&self, name: &str) -> Result<Option<Workspace>, AppError> {
         let ws = sqlx::query_as(
             r#"
@@ -43,7 +57,7 @@ impl AppState {
         WHERE id = $2 and (SELECT ws_id FROM users WHERE id = $1) = $2
         RETURNING id, name, owner_id, created_at
         "#;
-        sqlx::query_as(sql)
+        sqlx::query_as::<_, Workspace>(sql)
             .bind(owner_id)
             .bind(id)
             .fetch_one(&self.pool)
@@ -51,6 +65,34 @@ impl AppState {
 
         Ok(())
     }
+
+    /// hand the workspace over to another user of it
+    pub async fn transfer_workspace(&self, id: i64, owner_id: i64) -> Result<Workspace, AppError> {
+        let user = self
+            .find_user_by_id(owner_id)
+            .await?
+            .ok_or(AppError::UserNotFound(owner_id))?;
+        if user.ws_id != id {
+            return Err(AppError::InvalidWorkspaceOwner(format!(
+                "user {owner_id} belongs to workspace {}, not {id}",
+                user.ws_id
+            )));
+        }
+
+        let sql = r#"
+        UPDATE workspaces
+        SET owner_id = $1
+        WHERE id = $2
+        RETURNING id, name, owner_id, created_at
+        "#;
+        let ws: Option<Workspace> = sqlx::query_as(sql)
+            .bind(owner_id)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        ws.ok_or(AppError::WorkspaceNotFound(id))
+    }
 }
 
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq, Default)]
@@ -60,3 +102,27 @@ pub struct Workspace {
     pub owner_id: i64,
     pub created_at: DateTime<Utc>,
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{AppError, AppState};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn transfer_workspace_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::try_new_test().await?;
+        let ws = state.transfer_workspace(1, 2).await?;
+        assert_eq!(ws.owner_id, 2);
+        let workspaces = state.list_workspaces().await?;
+        let acme = workspaces.iter().find(|ws| ws.id == 1).unwrap();
+        assert_eq!(acme.owner_id, 2);
+
+        // the owner must be a user of the workspace
+        let ws = state.create_workspace("empty", 0).await?;
+        assert!(matches!(
+            state.transfer_workspace(ws.id, 1).await,
+            Err(AppError::InvalidWorkspaceOwner(_))
+        ));
+        Ok(())
+    }
+}
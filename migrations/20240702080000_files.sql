@@ -0,0 +1,11 @@
+-- uploaded files, one row per uploader of the same content
+CREATE TABLE IF NOT EXISTS files(
+  url text NOT NULL,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  uploader_id bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (url, uploader_id)
+);
+
+-- create gin index for messages for files, used by file access check
+CREATE INDEX IF NOT EXISTS files_index ON messages USING GIN(files);
@@ -0,0 +1,6 @@
+-- file size in bytes, used for workspace storage quota
+ALTER TABLE files
+  ADD COLUMN size bigint NOT NULL DEFAULT 0;
+
+-- create index for files for ws_id
+CREATE INDEX IF NOT EXISTS files_ws_id_index ON files(ws_id);
@@ -0,0 +1,11 @@
+-- audit of signin attempts, also used for progressive lockout
+CREATE TABLE IF NOT EXISTS signin_attempts(
+  id bigserial PRIMARY KEY,
+  email text NOT NULL,
+  ip text NOT NULL,
+  success boolean NOT NULL,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for signin_attempts for email and created_at order by created_at desc
+CREATE INDEX IF NOT EXISTS signin_attempts_email_index ON signin_attempts(email, created_at DESC);
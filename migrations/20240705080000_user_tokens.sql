@@ -0,0 +1,22 @@
+-- email is unverified until verified_at is set
+ALTER TABLE users
+  ADD COLUMN verified_at timestamptz;
+
+-- create user token kind: verify_email, reset_password
+CREATE TYPE user_token_kind AS ENUM(
+  'verify_email',
+  'reset_password'
+);
+
+-- single use tokens sent by mail, only the sha256 of the token is stored
+CREATE TABLE IF NOT EXISTS user_tokens(
+  token_hash char(64) PRIMARY KEY,
+  user_id bigint NOT NULL REFERENCES users(id),
+  kind user_token_kind NOT NULL,
+  expires_at timestamptz NOT NULL,
+  used_at timestamptz,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for user_tokens for user_id
+CREATE INDEX IF NOT EXISTS user_tokens_user_id_index ON user_tokens(user_id, kind);
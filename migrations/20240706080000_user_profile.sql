@@ -0,0 +1,5 @@
+-- avatar references an uploaded file url
+-- token_version is embedded in jwt, bumping it revokes all issued tokens
+ALTER TABLE users
+  ADD COLUMN avatar_url varchar(256),
+  ADD COLUMN token_version integer NOT NULL DEFAULT 0;
@@ -0,0 +1,35 @@
+-- pass the request id and traceparent set by chat_server in the transaction to the notifications
+CREATE OR REPLACE FUNCTION add_to_chat()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  RAISE NOTICE 'add_to_chat: %', NEW;
+  PERFORM
+    pg_notify('chat_updated', json_build_object('op', TG_OP, 'old', OLD, 'new', NEW, 'request_id', NULLIF(current_setting('chat.request_id', TRUE), ''), 'traceparent', NULLIF(current_setting('chat.traceparent', TRUE), ''))::text);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    RAISE NOTICE 'add_to_message: %', NEW;
+    -- select chat with chat_id in NEW
+    SELECT
+      members INTO USERS
+    FROM
+      chats
+    WHERE
+      id = NEW.chat_id;
+    PERFORM
+      pg_notify('chat_message_created', json_build_object('message', NEW, 'members', USERS, 'request_id', NULLIF(current_setting('chat.request_id', TRUE), ''), 'traceparent', NULLIF(current_setting('chat.traceparent', TRUE), ''))::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
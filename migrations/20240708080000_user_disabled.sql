@@ -0,0 +1,3 @@
+-- disabled users can't sign in and their issued tokens are revoked
+ALTER TABLE users
+  ADD COLUMN disabled_at timestamptz;
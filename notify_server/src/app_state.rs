@@ -0,0 +1,79 @@
+use std::{
+    ops::Deref,
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use anyhow::{Context, Result};
+use chat_core::{AppEvent, DecodingKeys, User};
+use dashmap::DashMap;
+use sqlx::PgPool;
+use tokio::sync::broadcast;
+
+use crate::Config;
+
+pub struct AppState {
+    /// the config loaded at startup, the keys are swapped on reload
+    pub(crate) config: Config,
+    pub(crate) dk: DecodingKeys,
+    /// the users table of chat_server, to reject revoked tokens and disabled users
+    pub(crate) pool: PgPool,
+    /// event senders of the connected users by user id
+    pub(crate) users: DashMap<i64, broadcast::Sender<Arc<AppEvent>>>,
+    /// whether the pg listener holds a live connection
+    pub(crate) listener_connected: AtomicBool,
+}
+
+impl AppState {
+    pub async fn try_new(config: Config) -> Result<Self> {
+        let dk = config.auth.decoding_keys().context("load pk failed")?;
+        let pool = config.db.connect().await?;
+        Ok(Self {
+            config,
+            dk: DecodingKeys::new(dk),
+            pool,
+            users: DashMap::new(),
+            listener_connected: AtomicBool::new(false),
+        })
+    }
+
+    /// swap the accepted keys of a validated config, other changes take effect on restart
+    pub fn reload(&self, config: &Config) -> Result<()> {
+        let dk = config.auth.decoding_keys().context("load pk failed")?;
+        self.dk.store(dk);
+        Ok(())
+    }
+
+    /// a token is revoked once the user's token version moved on, e.g. after a password change,
+    /// or the user is disabled, same as chat_server
+    pub(crate) async fn is_token_current(&self, user: &User) -> Result<bool> {
+        let version: Option<(i32,)> =
+            sqlx::query_as("SELECT token_version FROM users WHERE id = $1 AND disabled_at IS NULL")
+                .bind(user.id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(version.is_some_and(|(version,)| version == user.token_version))
+    }
+
+    /// tell the connected users the server is going away and end their event streams
+    pub fn shutdown(&self) {
+        let event = Arc::new(AppEvent::ServerRestarting);
+        for entry in self.users.iter() {
+            let _ = entry.send(event.clone());
+        }
+        // dropping the senders ends the streams once the last event is delivered
+        self.users.clear();
+    }
+
+    /// close the db pool once the checked out connections are returned
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+impl Deref for AppState {
+    type Target = Config;
+
+    fn deref(&self) -> &Self::Target {
+        &self.config
+    }
+}
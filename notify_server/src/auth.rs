@@ -0,0 +1,61 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{FromRequestParts, Query, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_auth::AuthBearer;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::AppState;
+
+#[derive(Debug, Deserialize)]
+struct TokenQuery {
+    token: String,
+}
+
+/// verify the token of chat_server, read from the bearer header or the `token` query
+/// since browsers can't set headers on an `EventSource`. Revoked tokens and disabled users are
+/// rejected when the stream is opened, a stream already open lasts until it reconnects
+pub async fn verify_token(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let token = match AuthBearer::from_request_parts(&mut parts, &state).await {
+        Ok(AuthBearer(token)) => token,
+        Err(e) => match Query::<TokenQuery>::from_request_parts(&mut parts, &state).await {
+            Ok(Query(query)) => query.token,
+            Err(_) => return e.into_response(),
+        },
+    };
+
+    let user = match state.dk.verify(&token) {
+        Ok(user) => user,
+        Err(e) => {
+            let msg = format!("verify token failed: {:?}", e);
+            warn!(msg);
+            return (StatusCode::FORBIDDEN, msg).into_response();
+        }
+    };
+    match state.is_token_current(&user).await {
+        Ok(true) => {
+            parts.extensions.insert(user);
+            next.run(Request::from_parts(parts, body)).await
+        }
+        Ok(false) => {
+            let msg = format!("token of user {} has been revoked", user.id);
+            warn!(msg);
+            (StatusCode::FORBIDDEN, msg).into_response()
+        }
+        Err(e) => {
+            let msg = format!("check token failed: {e}");
+            warn!(msg);
+            (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response()
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::{env, path::PathBuf};
+
+use anyhow::Result;
+use chat_core::{
+    env_vars, load_config, read_secret, redact_config, ConfigErrors, DbConfig, DecodingKey,
+    DecodingKeys, ListenConfig, LogConfig, TelemetryConfig,
+};
+use serde::{Deserialize, Serialize};
+
+/// env overrides start with it, e.g. `NOTIFY_LISTEN__PORT=6687`
+const ENV_PREFIX: &str = "NOTIFY";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub listen: ListenConfig,
+    #[serde(default)]
+    pub db: DbConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// public key of chat_server to verify the tokens of users, or read from `pk_file`
+    #[serde(default)]
+    pub pk: String,
+    #[serde(default)]
+    pub pk_file: Option<PathBuf>,
+    /// pems of previous public keys still accepted, e.g. while chat_server rotates its keys
+    #[serde(default)]
+    pub accepted_pks: Vec<String>,
+}
+
+impl AuthConfig {
+    pub fn decoding_keys(&self) -> Result<Vec<DecodingKey>> {
+        let pems = std::iter::once(&self.pk).chain(&self.accepted_pks);
+        DecodingKeys::load_all(pems.map(String::as_str))
+    }
+}
+
+impl Config {
+    /// load and validate the config
+    pub async fn try_new() -> Result<Self> {
+        let config = Self::load().await?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// defaults, then the yaml file of `$NOTIFY_CONFIG_PATH` if set, then the `NOTIFY_*` env
+    /// overrides
+    pub async fn load() -> Result<Self> {
+        let path = Self::path();
+        let mut config: Self = load_config(path.as_deref(), ENV_PREFIX, env_vars()).await?;
+        let auth = &mut config.auth;
+        read_secret(&mut auth.pk, auth.pk_file.as_ref(), "auth.pk_file").await?;
+        Ok(config)
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        env::var_os("NOTIFY_CONFIG_PATH").map(PathBuf::from)
+    }
+
+    /// the yaml file and the key file, a reload is triggered when one of them changes
+    pub fn watched_files(&self) -> Vec<PathBuf> {
+        [Self::path(), self.auth.pk_file.clone()]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = ConfigErrors::default();
+        self.listen.validate(&mut errors);
+        self.db.validate(&mut errors);
+        if self.auth.pk.is_empty() {
+            errors.add("auth.pk", "must be set, inline or by auth.pk_file");
+        } else if let Err(e) = DecodingKey::load(&self.auth.pk) {
+            errors.add("auth.pk", format!("invalid PEM: {e}"));
+        }
+        for (i, pem) in self.auth.accepted_pks.iter().enumerate() {
+            if let Err(e) = DecodingKey::load(pem) {
+                errors.add(
+                    &format!("auth.accepted_pks[{i}]"),
+                    format!("invalid PEM: {e}"),
+                );
+            }
+        }
+        self.log.validate(&mut errors);
+        errors.into_result()
+    }
+
+    /// the effective config as yaml with the db password redacted
+    pub fn redacted(&self) -> Result<String> {
+        redact_config(self, &[])
+    }
+}
@@ -0,0 +1,25 @@
+use std::sync::{atomic::Ordering, Arc};
+
+use anyhow::{bail, Result};
+use axum::extract::State;
+use chat_core::{check, HealthOutput};
+
+use crate::AppState;
+
+/// liveness, the process is up and serving requests
+pub(crate) async fn healthz_handler() -> HealthOutput {
+    HealthOutput::new(vec![])
+}
+
+/// readiness, the pg listener is connected so events reach the clients
+pub(crate) async fn readyz_handler(State(state): State<Arc<AppState>>) -> HealthOutput {
+    let listener = check("pg_listener", async { check_listener(&state) }).await;
+    HealthOutput::new(vec![listener])
+}
+
+fn check_listener(state: &AppState) -> Result<()> {
+    if !state.listener_connected.load(Ordering::Relaxed) {
+        bail!("pg listener is not connected");
+    }
+    Ok(())
+}
@@ -0,0 +1,189 @@
+mod app_state;
+mod auth;
+mod config;
+mod health;
+mod notif;
+mod sse;
+
+use std::sync::Arc;
+
+use axum::{
+    middleware::{from_fn, from_fn_with_state},
+    routing::get,
+    Router,
+};
+use chat_core::{metrics_handle, render_metrics, track_metrics};
+
+pub use app_state::AppState;
+pub use config::Config;
+pub use notif::setup_pg_listener;
+
+pub fn get_router(shared_app_state: Arc<AppState>) -> Router {
+    // install the recorder before any metric is recorded
+    metrics_handle();
+    Router::new()
+        .route("/events", get(sse::sse_handler))
+        .layer(from_fn_with_state(
+            shared_app_state.clone(),
+            auth::verify_token,
+        ))
+        .route("/online", get(|| async { "notify server online" }))
+        .route("/healthz", get(health::healthz_handler))
+        .route("/readyz", get(health::readyz_handler))
+        .route("/metrics", get(|| async { render_metrics() }))
+        .layer(from_fn(track_metrics))
+        .with_state(shared_app_state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notif::Notification;
+    use anyhow::Result;
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+    };
+    use chat_core::{EncodingKey, User};
+    use http_body_util::BodyExt;
+    use jwt_simple::prelude::Ed25519KeyPair;
+    use sqlx_db_tester::TestPg;
+    use std::{env, path::Path};
+    use tower::ServiceExt;
+
+    /// state on a test db of chat_server's migrations with one user and a token of it
+    async fn test_state() -> Result<(TestPg, Arc<AppState>, String)> {
+        dotenv::from_filename("./chat_server/.env").ok();
+        // the directory chat_server embeds its migrations from, wherever the tests run
+        let migrations = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../migrations"));
+        let tdb = TestPg::new(env::var("DATABASE_URL")?, migrations);
+        let pool = tdb.get_pool().await;
+        let (id,): (i64,) = sqlx::query_as(
+            "INSERT INTO users (ws_id, fullname, email, password_hash) VALUES (0, 'Tyr Chen', 'tchen@acme.org', '') RETURNING id",
+        )
+        .fetch_one(&pool)
+        .await?;
+
+        let key_pair = Ed25519KeyPair::generate();
+        let ek = EncodingKey::load(&key_pair.to_pem())?;
+        let mut config = Config::default();
+        config.db.url = tdb.url();
+        config.auth.pk = key_pair.public_key().to_pem();
+        let token = ek.sign(User::new(id, "Tyr Chen", "tchen@acme.org"))?;
+        let state = Arc::new(AppState::try_new(config).await?);
+        Ok((tdb, state, token))
+    }
+
+    #[tokio::test]
+    async fn events_without_token_should_be_rejected() -> Result<()> {
+        let (_tdb, state, _) = test_state().await?;
+        let req = Request::get("/events").body(Body::empty())?;
+        let res = get_router(state).oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn events_should_reject_revoked_token_and_disabled_user() -> Result<()> {
+        let (_tdb, state, token) = test_state().await?;
+        let app = get_router(state.clone());
+
+        sqlx::query("UPDATE users SET token_version = token_version + 1")
+            .execute(&state.pool)
+            .await?;
+        let req = Request::get(format!("/events?token={token}")).body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // the token is current again, but the user is disabled
+        sqlx::query("UPDATE users SET token_version = 0, disabled_at = NOW()")
+            .execute(&state.pool)
+            .await?;
+        let req = Request::get(format!("/events?token={token}")).body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn events_should_stream_dispatched_events() -> Result<()> {
+        let (_tdb, state, token) = test_state().await?;
+        let req = Request::get(format!("/events?token={token}")).body(Body::empty())?;
+        let res = get_router(state.clone()).oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/event-stream");
+
+        let payload = r#"{"message":{"id":1,"chat_id":1,"sender_id":2,"content":"hi","files":[],"created_at":"2024-06-09T10:00:00.123456+00:00"},"members":[1,2]}"#;
+        for notification in Notification::load("chat_message_created", payload)? {
+            state.dispatch(notification);
+        }
+
+        let mut body = res.into_body();
+        let frame = body.frame().await.expect("event frame")?;
+        let data = String::from_utf8(frame.into_data().expect("data frame").to_vec())?;
+        assert!(data.starts_with("event: new_message\n"));
+        assert!(data.contains(r#""content":"hi""#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn readyz_should_report_pg_listener() -> Result<()> {
+        let (_tdb, state, _) = test_state().await?;
+        let app = get_router(state.clone());
+        let req = Request::get("/healthz").body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = Request::get("/readyz").body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        state
+            .listener_connected
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        let req = Request::get("/readyz").body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await?.to_bytes();
+        let output: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(output["checks"][0]["name"], "pg_listener");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn metrics_should_count_clients_and_delivered_events() -> Result<()> {
+        let (_tdb, state, token) = test_state().await?;
+        let app = get_router(state.clone());
+        let req = Request::get(format!("/events?token={token}")).body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+
+        let payload = r#"{"message":{"id":1,"chat_id":1,"sender_id":2,"content":"hi","files":[],"created_at":"2024-06-09T10:00:00.123456+00:00"},"members":[1,2]}"#;
+        for notification in Notification::load("chat_message_created", payload)? {
+            state.dispatch(notification);
+        }
+        res.into_body().frame().await.expect("event frame")?;
+
+        let req = Request::get("/metrics").body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        let body = res.into_body().collect().await?.to_bytes();
+        let body = String::from_utf8(body.to_vec())?;
+        assert!(body.contains("sse_clients"));
+        assert!(body.contains(r#"events_delivered_total{event="new_message"}"#));
+        assert!(body.contains(r#"http_requests_total{method="GET",route="/events",status="200"}"#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_should_send_restarting_and_end_streams() -> Result<()> {
+        let (_tdb, state, token) = test_state().await?;
+        let req = Request::get(format!("/events?token={token}")).body(Body::empty())?;
+        let res = get_router(state.clone()).oneshot(req).await?;
+        state.shutdown();
+
+        let body = res.into_body().collect().await?.to_bytes();
+        let data = String::from_utf8(body.to_vec())?;
+        assert!(data.starts_with("event: server_restarting\n"));
+        assert!(state.users.is_empty());
+        Ok(())
+    }
+}
@@ -1,3 +1,60 @@
-fn main() {
-    println!("Hello, world!");
+use anyhow::Result;
+use chat_core::{init_tracing, spawn_reloader, LogFilter, Shutdown};
+use dotenv::dotenv;
+use notify_server::{get_router, setup_pg_listener, AppState, Config};
+use std::{env, sync::Arc, time::Duration};
+use tokio::net::TcpListener;
+use tracing::info;
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+
+    if env::args().any(|arg| arg == "--print-config") {
+        print!("{}", Config::load().await?.redacted()?);
+        return Ok(());
+    }
+
+    let config = Config::try_new().await?;
+    let telemetry = init_tracing("notify_server", &config.log, &config.telemetry)?;
+    let addr = format!("{}:{}", config.listen.host, config.listen.port);
+    let drain_timeout = Duration::from_secs(config.listen.drain_timeout);
+    let watched_files = config.watched_files();
+
+    let app_state = Arc::new(AppState::try_new(config).await?);
+    setup_pg_listener(app_state.clone()).await?;
+
+    // reload the log filter and the accepted keys on SIGHUP or when the config files change
+    let state = app_state.clone();
+    let log_filter = telemetry.log_filter();
+    spawn_reloader(watched_files, RELOAD_INTERVAL, move || {
+        let state = state.clone();
+        let log_filter = log_filter.clone();
+        async move {
+            let config = Config::load().await?;
+            config.validate()?;
+            let filter = LogFilter::parse(&config.log.directives())?;
+            state.reload(&config)?;
+            log_filter.set(filter)
+        }
+    });
+    let app = get_router(app_state.clone());
+
+    let listener = TcpListener::bind(&addr).await?;
+    info!("notify server listen on {addr}");
+    let shutdown = Shutdown::listen();
+    let signal = shutdown.clone().wait();
+    let state = app_state.clone();
+    let server =
+        axum::serve(listener, app.into_make_service()).with_graceful_shutdown(async move {
+            signal.await;
+            state.shutdown();
+        });
+    shutdown.drain(server, drain_timeout).await?;
+    app_state.close().await;
+    info!("notify server quit");
+
+    Ok(())
 }
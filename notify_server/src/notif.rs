@@ -0,0 +1,216 @@
+use std::{
+    collections::HashSet,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use anyhow::Result;
+use chat_core::{set_parent_from_traceparent, AppEvent, Chat, Message};
+use serde::Deserialize;
+use sqlx::postgres::PgListener;
+use tracing::{info, info_span, warn};
+
+use crate::AppState;
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// request id and `traceparent` of the chat_server request which fired the trigger
+#[derive(Debug, Default, Deserialize)]
+struct TraceContext {
+    request_id: Option<String>,
+    traceparent: Option<String>,
+}
+
+/// payload of the `chat_updated` trigger
+#[derive(Debug, Deserialize)]
+struct ChatUpdated {
+    op: String,
+    old: Option<Chat>,
+    new: Option<Chat>,
+}
+
+/// payload of the `chat_message_created` trigger
+#[derive(Debug, Deserialize)]
+struct ChatMessageCreated {
+    message: Message,
+    members: Vec<i64>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Notification {
+    pub(crate) user_ids: HashSet<i64>,
+    pub(crate) event: Arc<AppEvent>,
+}
+
+/// listen to the chat triggers and dispatch the events to the connected users
+pub async fn setup_pg_listener(state: Arc<AppState>) -> Result<()> {
+    let mut listener = PgListener::connect_with(&state.pool).await?;
+    listener.listen("chat_updated").await?;
+    listener.listen("chat_message_created").await?;
+    state.listener_connected.store(true, Ordering::Relaxed);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.try_recv().await {
+                Ok(Some(notif)) => state.handle_notification(notif.channel(), notif.payload()),
+                Ok(None) => {
+                    warn!("pg listener connection lost");
+                    reconnect(&mut listener, &state).await;
+                }
+                Err(e) => {
+                    warn!("pg listener failed: {e}");
+                    reconnect(&mut listener, &state).await;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// reconnect the listener, which listens to its channels again, and track the state for `/readyz`
+async fn reconnect(listener: &mut PgListener, state: &AppState) {
+    state.listener_connected.store(false, Ordering::Relaxed);
+    loop {
+        match sqlx::query("SELECT 1").execute(&mut *listener).await {
+            Ok(_) => {
+                info!("pg listener reconnected");
+                state.listener_connected.store(true, Ordering::Relaxed);
+                return;
+            }
+            Err(e) => {
+                warn!("pg listener reconnect failed: {e}");
+                tokio::time::sleep(RECONNECT_INTERVAL).await;
+            }
+        }
+    }
+}
+
+impl AppState {
+    /// dispatch the notification in a span continuing the trace of the chat_server request
+    fn handle_notification(&self, channel: &str, payload: &str) {
+        let trace: TraceContext = serde_json::from_str(payload).unwrap_or_default();
+        let span = info_span!(
+            "notification",
+            channel,
+            request_id = trace.request_id.as_deref().unwrap_or_default(),
+        );
+        if let Some(traceparent) = &trace.traceparent {
+            set_parent_from_traceparent(&span, traceparent);
+        }
+        let _enter = span.enter();
+
+        info!(channel, "received notification");
+        if channel == "chat_message_created" {
+            metrics::counter!("messages_created_total").increment(1);
+        }
+        match Notification::load(channel, payload) {
+            Ok(notifications) => {
+                for notification in notifications {
+                    self.dispatch(notification);
+                }
+            }
+            Err(e) => warn!(channel, "invalid notification: {e}"),
+        }
+    }
+
+    pub(crate) fn dispatch(&self, notification: Notification) {
+        for user_id in notification.user_ids {
+            let Some(tx) = self.users.get(&user_id) else {
+                continue;
+            };
+            if tx.send(notification.event.clone()).is_err() {
+                // every subscriber of the user is gone
+                metrics::counter!("events_dropped_total", "reason" => "disconnected").increment(1);
+                drop(tx);
+                self.users
+                    .remove_if(&user_id, |_, tx| tx.receiver_count() == 0);
+            }
+        }
+    }
+}
+
+impl Notification {
+    pub(crate) fn load(channel: &str, payload: &str) -> Result<Vec<Self>> {
+        match channel {
+            "chat_updated" => {
+                let payload: ChatUpdated = serde_json::from_str(payload)?;
+                let old = payload.old.as_ref().map(members).unwrap_or_default();
+                let new = payload.new.as_ref().map(members).unwrap_or_default();
+                let notifications = match (payload.op.as_str(), payload.old, payload.new) {
+                    ("INSERT", _, Some(chat)) => vec![Self::new(new, AppEvent::NewChat(chat))],
+                    ("UPDATE", _, Some(chat)) => {
+                        let added = new.difference(&old).copied().collect();
+                        let removed = old.difference(&new).copied().collect();
+                        vec![
+                            Self::new(added, AppEvent::AddToChat(chat.clone())),
+                            Self::new(removed, AppEvent::RemoveFromChat(chat)),
+                        ]
+                    }
+                    ("DELETE", Some(chat), _) => {
+                        vec![Self::new(old, AppEvent::RemoveFromChat(chat))]
+                    }
+                    (op, _, _) => anyhow::bail!("unexpected chat op {op}"),
+                };
+                Ok(notifications
+                    .into_iter()
+                    .filter(|n| !n.user_ids.is_empty())
+                    .collect())
+            }
+            "chat_message_created" => {
+                let payload: ChatMessageCreated = serde_json::from_str(payload)?;
+                let user_ids = payload.members.into_iter().collect();
+                Ok(vec![Self::new(
+                    user_ids,
+                    AppEvent::NewMessage(payload.message),
+                )])
+            }
+            _ => anyhow::bail!("unexpected channel {channel}"),
+        }
+    }
+
+    fn new(user_ids: HashSet<i64>, event: AppEvent) -> Self {
+        Self {
+            user_ids,
+            event: Arc::new(event),
+        }
+    }
+}
+
+fn members(chat: &Chat) -> HashSet<i64> {
+    chat.members.iter().copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAT: &str = r#"{"id":1,"ws_id":1,"name":null,"type":"group","members":[1,2,3],"created_at":"2024-06-09T10:00:00.123456+00:00"}"#;
+
+    #[test]
+    fn chat_updated_should_notify_added_and_removed_members() -> Result<()> {
+        let new = CHAT.replace("[1,2,3]", "[1,3,4]");
+        let payload = format!(r#"{{"op":"UPDATE","old":{CHAT},"new":{new}}}"#);
+        let notifications = Notification::load("chat_updated", &payload)?;
+        assert_eq!(notifications.len(), 2);
+        assert_eq!(notifications[0].user_ids, HashSet::from([4]));
+        assert_eq!(notifications[0].event.name(), "add_to_chat");
+        assert_eq!(notifications[1].user_ids, HashSet::from([2]));
+        assert_eq!(notifications[1].event.name(), "remove_from_chat");
+
+        let payload = format!(r#"{{"op":"INSERT","old":null,"new":{CHAT}}}"#);
+        let notifications = Notification::load("chat_updated", &payload)?;
+        assert_eq!(notifications[0].user_ids, HashSet::from([1, 2, 3]));
+        assert_eq!(notifications[0].event.name(), "new_chat");
+        Ok(())
+    }
+
+    #[test]
+    fn chat_message_created_should_notify_members() -> Result<()> {
+        let payload = r#"{"message":{"id":1,"chat_id":1,"sender_id":1,"content":"hi","files":[],"created_at":"2024-06-09T10:00:00.123456+00:00"},"members":[1,2]}"#;
+        let notifications = Notification::load("chat_message_created", payload)?;
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].user_ids, HashSet::from([1, 2]));
+        assert_eq!(notifications[0].event.name(), "new_message");
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::State,
+    response::{
+        sse::{Event, KeepAlive},
+        Sse,
+    },
+    Extension,
+};
+use chat_core::User;
+use futures::Stream;
+use metrics::{counter, gauge};
+use tokio::sync::broadcast;
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    StreamExt,
+};
+use tracing::{info, warn};
+
+use crate::AppState;
+
+const CHANNEL_CAPACITY: usize = 256;
+
+/// tracks the `sse_clients` gauge for the lifetime of an event stream
+struct ClientGuard;
+
+impl ClientGuard {
+    fn new() -> Self {
+        gauge!("sse_clients").increment(1);
+        Self
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        gauge!("sse_clients").decrement(1);
+    }
+}
+
+/// stream the events of the user, the event name is `AppEvent::name` and the data the event json
+pub(crate) async fn sse_handler(
+    Extension(user): Extension<User>,
+    State(state): State<Arc<AppState>>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let rx = state
+        .users
+        .entry(user.id)
+        .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+        .subscribe();
+    info!(user_id = user.id, "user subscribed");
+
+    // lagged receivers skip the dropped events
+    let guard = ClientGuard::new();
+    let stream = BroadcastStream::new(rx)
+        .filter_map(move |event| {
+            let _guard = &guard;
+            match event {
+                Ok(event) => Some(event),
+                Err(BroadcastStreamRecvError::Lagged(n)) => {
+                    warn!(user_id = user.id, dropped = n, "user lagged behind");
+                    counter!("events_dropped_total", "reason" => "lagged").increment(n);
+                    None
+                }
+            }
+        })
+        .map(|event| {
+            counter!("events_delivered_total", "event" => event.name()).increment(1);
+            Event::default()
+                .event(event.name())
+                .json_data(event.as_ref())
+        });
+
+    Sse::new(stream).keep_alive(
+        KeepAlive::new()
+            .interval(Duration::from_secs(1))
+            .text("keep-alive-text"),
+    )
+}